@@ -1,9 +1,32 @@
+use std::env;
 use std::path::Path;
 
+/// The build-time configurations given by the environment variables, and their default values.
+const CONFIGS: &[(&str, usize)] = &[
+    ("PERCPU_MAX_CPU_NUM", 256),
+    ("PERCPU_DYNAMIC_CHUNK_SIZE", 4096),
+];
+
 fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    for &(name, default) in CONFIGS {
+        println!("cargo:rerun-if-env-changed={name}");
+        let value = match env::var(name) {
+            Ok(value) => match value.parse::<usize>() {
+                Ok(value) if value > 0 => value,
+                _ => panic!("{name} must be a positive integer, got {value:?}"),
+            },
+            Err(_) => default,
+        };
+        println!("cargo:rustc-env={name}={value}");
+    }
+
     if cfg!(target_os = "linux") && cfg!(not(feature = "sp-naive")) {
         let ld_script_path = Path::new(std::env!("CARGO_MANIFEST_DIR")).join("test_percpu.x");
+        // `test_percpu.x` links the `.percpu` section at address 0, below the image base of the executable, which
+        // rust-lld (the default linker of rustc on x86_64 Linux) rejects, so use GNU ld.
         println!("cargo:rustc-link-arg-tests=-no-pie");
+        println!("cargo:rustc-link-arg-tests=-fuse-ld=bfd");
         println!("cargo:rustc-link-arg-tests=-T{}", ld_script_path.display());
     }
 }
@@ -0,0 +1,321 @@
+//! CPU masks, and the global masks of possible, present and online CPUs.
+
+use core::fmt;
+use core::ops::{BitAnd, BitOr, Not};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// The number of CPUs in each word of the bitmap.
+const WORD_BITS: usize = usize::BITS as usize;
+const WORDS: usize = CpuMask::CAPACITY.div_ceil(WORD_BITS);
+
+/// A set of CPUs, represented as a bitmap of CPU IDs.
+///
+/// # Examples
+///
+/// ```rust
+/// use percpu::CpuMask;
+///
+/// let mut mask = CpuMask::new();
+/// mask.set(1);
+/// mask.set(3);
+/// assert!(mask.contains(3));
+/// assert_eq!(mask.weight(), 2);
+/// assert_eq!(mask.iter().collect::<Vec<_>>(), [1, 3]);
+/// assert_eq!(mask & CpuMask::first(2), [1].into_iter().collect());
+/// ```
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct CpuMask {
+    bits: [usize; WORDS],
+}
+
+impl CpuMask {
+    /// The maximum number of CPUs in a mask. CPU IDs must be less than it.
+    ///
+    /// It is `256` by default, and can be configured with the `PERCPU_MAX_CPU_NUM` environment variable at build time.
+    /// The per-CPU data areas can not be initialized for more CPUs.
+    pub const CAPACITY: usize = match usize::from_str_radix(env!("PERCPU_MAX_CPU_NUM"), 10) {
+        Ok(num) => num,
+        Err(_) => panic!("invalid PERCPU_MAX_CPU_NUM"),
+    };
+
+    /// Creates an empty mask.
+    pub const fn new() -> Self {
+        Self { bits: [0; WORDS] }
+    }
+
+    /// Creates a mask of the first `num` CPUs, i.e., CPU `0` to `num - 1`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `num` exceeds [`CAPACITY`](Self::CAPACITY).
+    pub fn first(num: usize) -> Self {
+        assert!(num <= Self::CAPACITY, "too many CPUs: {}", num);
+        let mut mask = Self::new();
+        for (i, word) in mask.bits.iter_mut().enumerate() {
+            *word = match num.saturating_sub(i * WORD_BITS) {
+                0 => 0,
+                n if n >= WORD_BITS => usize::MAX,
+                n => (1 << n) - 1,
+            };
+        }
+        mask
+    }
+
+    /// Adds the CPU to the mask.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `cpu_id` is not less than [`CAPACITY`](Self::CAPACITY).
+    pub fn set(&mut self, cpu_id: usize) {
+        assert!(cpu_id < Self::CAPACITY, "invalid CPU ID: {}", cpu_id);
+        self.bits[cpu_id / WORD_BITS] |= 1 << (cpu_id % WORD_BITS);
+    }
+
+    /// Removes the CPU from the mask.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `cpu_id` is not less than [`CAPACITY`](Self::CAPACITY).
+    pub fn clear(&mut self, cpu_id: usize) {
+        assert!(cpu_id < Self::CAPACITY, "invalid CPU ID: {}", cpu_id);
+        self.bits[cpu_id / WORD_BITS] &= !(1 << (cpu_id % WORD_BITS));
+    }
+
+    /// Whether the CPU is in the mask.
+    pub fn contains(&self, cpu_id: usize) -> bool {
+        cpu_id < Self::CAPACITY && self.bits[cpu_id / WORD_BITS] & (1 << (cpu_id % WORD_BITS)) != 0
+    }
+
+    /// Whether the mask is empty.
+    pub fn is_empty(&self) -> bool {
+        self.bits.iter().all(|&word| word == 0)
+    }
+
+    /// Returns the number of CPUs in the mask.
+    pub fn weight(&self) -> usize {
+        self.bits
+            .iter()
+            .map(|word| word.count_ones() as usize)
+            .sum()
+    }
+
+    /// Returns the lowest CPU ID in the mask, or `None` if the mask is empty.
+    pub fn first_set(&self) -> Option<usize> {
+        self.iter().next()
+    }
+
+    /// Returns an iterator over the CPU IDs in the mask, in the ascending order.
+    pub fn iter(&self) -> CpuMaskIter {
+        CpuMaskIter { mask: *self }
+    }
+}
+
+impl fmt::Debug for CpuMask {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
+
+impl BitAnd for CpuMask {
+    type Output = Self;
+
+    fn bitand(mut self, rhs: Self) -> Self {
+        for (word, rhs) in self.bits.iter_mut().zip(rhs.bits) {
+            *word &= rhs;
+        }
+        self
+    }
+}
+
+impl BitOr for CpuMask {
+    type Output = Self;
+
+    fn bitor(mut self, rhs: Self) -> Self {
+        for (word, rhs) in self.bits.iter_mut().zip(rhs.bits) {
+            *word |= rhs;
+        }
+        self
+    }
+}
+
+impl Not for CpuMask {
+    type Output = Self;
+
+    fn not(mut self) -> Self {
+        for word in self.bits.iter_mut() {
+            *word = !*word;
+        }
+        // The CPUs beyond the capacity are never in the mask.
+        let rem = Self::CAPACITY % WORD_BITS;
+        if rem != 0 {
+            self.bits[WORDS - 1] &= (1 << rem) - 1;
+        }
+        self
+    }
+}
+
+impl FromIterator<usize> for CpuMask {
+    fn from_iter<I: IntoIterator<Item = usize>>(iter: I) -> Self {
+        let mut mask = Self::new();
+        for cpu_id in iter {
+            mask.set(cpu_id);
+        }
+        mask
+    }
+}
+
+impl IntoIterator for CpuMask {
+    type Item = usize;
+    type IntoIter = CpuMaskIter;
+
+    fn into_iter(self) -> CpuMaskIter {
+        self.iter()
+    }
+}
+
+impl IntoIterator for &CpuMask {
+    type Item = usize;
+    type IntoIter = CpuMaskIter;
+
+    fn into_iter(self) -> CpuMaskIter {
+        self.iter()
+    }
+}
+
+/// An iterator over the CPU IDs in a [`CpuMask`], in the ascending order.
+#[derive(Clone)]
+pub struct CpuMaskIter {
+    mask: CpuMask,
+}
+
+impl Iterator for CpuMaskIter {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        let (i, word) = self
+            .mask
+            .bits
+            .iter_mut()
+            .enumerate()
+            .find(|(_, word)| **word != 0)?;
+        let bit = word.trailing_zeros() as usize;
+        *word &= *word - 1;
+        Some(i * WORD_BITS + bit)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.mask.weight();
+        (len, Some(len))
+    }
+}
+
+impl ExactSizeIterator for CpuMaskIter {}
+
+/// A [`CpuMask`] that can be updated concurrently.
+struct AtomicCpuMask {
+    bits: [AtomicUsize; WORDS],
+}
+
+impl AtomicCpuMask {
+    const fn new() -> Self {
+        Self {
+            bits: [const { AtomicUsize::new(0) }; WORDS],
+        }
+    }
+
+    fn load(&self) -> CpuMask {
+        let mut mask = CpuMask::new();
+        for (word, bits) in mask.bits.iter_mut().zip(&self.bits) {
+            *word = bits.load(Ordering::Acquire);
+        }
+        mask
+    }
+
+    fn store(&self, mask: CpuMask) {
+        for (bits, word) in self.bits.iter().zip(mask.bits) {
+            bits.store(word, Ordering::Release);
+        }
+    }
+
+    fn contains(&self, cpu_id: usize) -> bool {
+        cpu_id < CpuMask::CAPACITY
+            && self.bits[cpu_id / WORD_BITS].load(Ordering::Acquire) & (1 << (cpu_id % WORD_BITS))
+                != 0
+    }
+
+    fn assign(&self, cpu_id: usize, value: bool) {
+        let bit = 1 << (cpu_id % WORD_BITS);
+        if value {
+            self.bits[cpu_id / WORD_BITS].fetch_or(bit, Ordering::AcqRel);
+        } else {
+            self.bits[cpu_id / WORD_BITS].fetch_and(!bit, Ordering::AcqRel);
+        }
+    }
+}
+
+static CPU_POSSIBLE: AtomicCpuMask = AtomicCpuMask::new();
+static CPU_PRESENT: AtomicCpuMask = AtomicCpuMask::new();
+static CPU_ONLINE: AtomicCpuMask = AtomicCpuMask::new();
+
+/// Initializes the global masks on initialization of the per-CPU data areas for `max_cpu_num` CPUs, of which the first
+/// `online_num` CPUs are online.
+pub(crate) fn init_masks(max_cpu_num: usize, online_num: usize) {
+    let possible = CpuMask::first(max_cpu_num);
+    CPU_POSSIBLE.store(possible);
+    CPU_PRESENT.store(possible);
+    CPU_ONLINE.store(CpuMask::first(online_num));
+}
+
+/// Returns the mask of possible CPUs, i.e., the CPUs whose per-CPU data areas are initialized by
+/// [`init`](crate::init) or its variants. It never changes after the initialization.
+pub fn cpu_possible_mask() -> CpuMask {
+    CPU_POSSIBLE.load()
+}
+
+/// Returns the mask of present CPUs, which is the same as the possible CPUs after the initialization, and updated
+/// with [`set_cpu_present`] on CPU hotplug.
+pub fn cpu_present_mask() -> CpuMask {
+    CPU_PRESENT.load()
+}
+
+/// Returns the mask of online CPUs.
+///
+/// All possible CPUs are online after [`init`](crate::init) or [`try_init`](crate::try_init). Only CPU 0 is online
+/// after [`init_primary`](crate::init_primary), and each secondary CPU becomes online in
+/// [`init_secondary`](crate::init_secondary). It can also be updated with [`set_cpu_online`].
+pub fn cpu_online_mask() -> CpuMask {
+    CPU_ONLINE.load()
+}
+
+/// Whether the CPU is online.
+pub fn cpu_online(cpu_id: usize) -> bool {
+    CPU_ONLINE.contains(cpu_id)
+}
+
+/// Marks the CPU as present or not present. A CPU that is not present is not online either.
+///
+/// # Panics
+///
+/// Panics if the CPU is not possible.
+pub fn set_cpu_present(cpu_id: usize, present: bool) {
+    assert!(CPU_POSSIBLE.contains(cpu_id), "invalid CPU ID: {}", cpu_id);
+    CPU_PRESENT.assign(cpu_id, present);
+    if !present {
+        CPU_ONLINE.assign(cpu_id, false);
+    }
+}
+
+/// Marks the CPU as online or offline. The per-CPU data on offline CPUs is skipped by the remote iteration and
+/// aggregation methods of [`PerCpuVar`](crate::PerCpuVar) and [`PerCpuPrimitive`](crate::PerCpuPrimitive).
+///
+/// # Panics
+///
+/// Panics if the CPU is not present.
+pub fn set_cpu_online(cpu_id: usize, online: bool) {
+    assert!(
+        CPU_PRESENT.contains(cpu_id),
+        "CPU {} is not present",
+        cpu_id
+    );
+    CPU_ONLINE.assign(cpu_id, online);
+}
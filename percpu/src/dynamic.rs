@@ -0,0 +1,409 @@
+//! Per-CPU data allocated at runtime, in a chunk reserved in the per-CPU data area of every CPU.
+
+use core::marker::PhantomData;
+use core::mem::{align_of, size_of};
+
+use crate::var::area_num;
+use crate::{CpuMask, PerCpuPrimitive, PerCpuPtr, PerCpuVar, Primitive, PrimitiveInt};
+
+/// The size of the chunk reserved in the per-CPU data area of every CPU for [`alloc_percpu`].
+///
+/// It is `4096` by default, and can be configured with the `PERCPU_DYNAMIC_CHUNK_SIZE` environment variable at build
+/// time, which must be a multiple of 16.
+#[doc(cfg(feature = "dynamic"))]
+pub const DYNAMIC_CHUNK_SIZE: usize =
+    match usize::from_str_radix(env!("PERCPU_DYNAMIC_CHUNK_SIZE"), 10) {
+        Ok(size) if size % UNIT_SIZE == 0 => size,
+        _ => panic!("invalid PERCPU_DYNAMIC_CHUNK_SIZE, which must be a multiple of 16"),
+    };
+
+/// The allocation unit in the chunk.
+const UNIT_SIZE: usize = 16;
+
+const UNIT_NUM: usize = DYNAMIC_CHUNK_SIZE / UNIT_SIZE;
+
+const CHUNK_ALIGN: usize = 64;
+
+#[repr(C, align(64))]
+struct Chunk {
+    data: [u8; DYNAMIC_CHUNK_SIZE],
+}
+
+use crate as percpu;
+
+#[percpu_macros::def_percpu(zeroed)]
+static DYNAMIC_CHUNK: Chunk = Chunk {
+    data: [0; DYNAMIC_CHUNK_SIZE],
+};
+
+/// Initializes the value at the given address to its default value, or drops it.
+type ValueFn = unsafe fn(usize);
+
+/// A live allocation in the chunk.
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "sp-naive", allow(dead_code))] // the global data is never (re)initialized
+struct Allocation {
+    init: ValueFn,
+    drop: ValueFn,
+}
+
+/// The allocation state of the chunk.
+struct ChunkState {
+    /// Allocated units in the chunk, one bit for each unit.
+    bitmap: [u64; UNIT_NUM.div_ceil(64)],
+    /// The live allocations, indexed by their first units.
+    allocations: [Option<Allocation>; UNIT_NUM],
+    /// The CPUs whose per-CPU data areas are initialized, where the live allocations hold valid values.
+    areas: CpuMask,
+}
+
+impl ChunkState {
+    /// Returns the CPUs whose per-CPU data areas hold valid values of the live allocations.
+    fn areas(&self) -> CpuMask {
+        if cfg!(feature = "sp-naive") {
+            // All CPUs share the same global data, which is always initialized.
+            CpuMask::first(area_num())
+        } else {
+            self.areas
+        }
+    }
+
+    /// Allocates `size` bytes aligned to `align` in the chunk, and returns the offset relative to the chunk.
+    fn alloc(&mut self, size: usize, align: usize, allocation: Allocation) -> Option<usize> {
+        if align > CHUNK_ALIGN {
+            return None;
+        }
+        let num = size.div_ceil(UNIT_SIZE).max(1);
+        let step = align.div_ceil(UNIT_SIZE).max(1);
+        let is_allocated = |bitmap: &[u64], i: usize| bitmap[i / 64] & (1 << (i % 64)) != 0;
+
+        let mut start = 0;
+        while start + num <= UNIT_NUM {
+            match (start..start + num).rfind(|&i| is_allocated(&self.bitmap, i)) {
+                Some(i) => start = (i + 1).next_multiple_of(step),
+                None => {
+                    for i in start..start + num {
+                        self.bitmap[i / 64] |= 1 << (i % 64);
+                    }
+                    self.allocations[start] = Some(allocation);
+                    return Some(start * UNIT_SIZE);
+                }
+            }
+        }
+        None
+    }
+
+    /// Frees `size` bytes at `offset` relative to the chunk, which are allocated by [`ChunkState::alloc`].
+    fn free(&mut self, offset: usize, size: usize) {
+        let start = offset / UNIT_SIZE;
+        let num = size.div_ceil(UNIT_SIZE).max(1);
+        for i in start..start + num {
+            self.bitmap[i / 64] &= !(1 << (i % 64));
+        }
+        self.allocations[start] = None;
+    }
+}
+
+/// The allocation state of the chunk, which also serializes the accesses to the live allocations on other CPUs, i.e.,
+/// initializing and dropping them, with the (re)initialization of the per-CPU data areas.
+static STATE: spin::Mutex<ChunkState> = spin::Mutex::new(ChunkState {
+    bitmap: [0; UNIT_NUM.div_ceil(64)],
+    allocations: [None; UNIT_NUM],
+    areas: CpuMask::new(),
+});
+
+unsafe fn init_default<T: Default>(addr: usize) {
+    (addr as *mut T).write(T::default());
+}
+
+unsafe fn drop_value<T>(addr: usize) {
+    (addr as *mut T).drop_in_place();
+}
+
+/// (Re)initializes the per-CPU data area on the given CPU at `base` with `init`, e.g., copying the initial values
+/// of the per-CPU static variables, which zeroes the chunk.
+///
+/// If the area has been initialized, the live allocations in it are dropped before, and they are initialized to their
+/// default values again after. The allocations and deallocations wait until it is done.
+///
+/// # Safety
+///
+/// Caller must ensure that the per-CPU data area at `base` is not being accessed.
+#[cfg(not(feature = "sp-naive"))]
+pub(crate) unsafe fn init_area(cpu_id: usize, base: usize, init: impl FnOnce()) {
+    let mut state = STATE.lock();
+    let chunk = base + DYNAMIC_CHUNK.offset();
+    let allocations = state.allocations.iter().enumerate();
+    let allocations = allocations.filter_map(|(unit, a)| a.map(|a| (chunk + unit * UNIT_SIZE, a)));
+    if state.areas.contains(cpu_id) {
+        for (addr, allocation) in allocations.clone() {
+            (allocation.drop)(addr);
+        }
+    }
+    init();
+    for (addr, allocation) in allocations {
+        (allocation.init)(addr);
+    }
+    state.areas.set(cpu_id);
+}
+
+/// Allocates per-CPU data of type `T` at runtime, which is initialized to `T::default()` on every CPU.
+///
+/// Returns `None` if there is no enough space in the chunk reserved in the per-CPU data areas, whose size is
+/// [`DYNAMIC_CHUNK_SIZE`], or `T` is aligned to more than 64 bytes.
+///
+/// It should be called after the per-CPU data areas are initialized. The per-CPU data on the CPUs that are initialized
+/// later by [`init_secondary`](crate::init_secondary) is initialized to `T::default()` then, and the per-CPU data reset
+/// by [`reset_cpu_area`](crate::reset_cpu_area) is dropped and initialized to `T::default()` again.
+///
+/// `T::default()` is called with the allocation state locked, so it must not allocate or free per-CPU data, which
+/// deadlocks.
+///
+/// # Panics
+///
+/// Panics if the per-CPU data areas are not initialized.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// percpu::init(4);
+/// percpu::set_local_thread_pointer(0);
+///
+/// let counter = percpu::alloc_percpu::<u64>().unwrap();
+/// counter.add_current(1);
+/// assert_eq!(counter.read_current(), 1);
+/// assert_eq!(unsafe { *counter.remote_ptr(1) }, 0);
+/// ```
+#[doc(cfg(feature = "dynamic"))]
+pub fn alloc_percpu<T: Default>() -> Option<PerCpuBox<T>> {
+    assert!(area_num() != 0, "per-CPU allocation before percpu::init");
+    let allocation = Allocation {
+        init: init_default::<T>,
+        drop: drop_value::<T>,
+    };
+    let mut state = STATE.lock();
+    let offset =
+        DYNAMIC_CHUNK.offset() + state.alloc(size_of::<T>(), align_of::<T>(), allocation)?;
+    // Only the initialized per-CPU data areas, the others are initialized along with the areas.
+    for cpu_id in state.areas().iter() {
+        let ptr = (crate::percpu_area_base(cpu_id) + offset) as *mut T;
+        unsafe { ptr.write(T::default()) };
+    }
+    Some(PerCpuBox {
+        ptr: unsafe { PerCpuPtr::from_offset(offset) },
+        _phantom: PhantomData,
+    })
+}
+
+/// Per-CPU data allocated at runtime by [`alloc_percpu`].
+///
+/// It is accessed in the same way as the per-CPU static variables defined with [`def_percpu`], through the
+/// [`PerCpuPtr`] to the allocated per-CPU data. The per-CPU data on all CPUs is dropped, and the space is freed when
+/// it is dropped, where the drop of `T` must not allocate or free per-CPU data, which deadlocks.
+///
+/// [`def_percpu`]: crate::def_percpu
+#[doc(cfg(feature = "dynamic"))]
+pub struct PerCpuBox<T> {
+    ptr: PerCpuPtr<T>,
+    _phantom: PhantomData<T>,
+}
+
+impl<T> PerCpuBox<T> {
+    /// Returns the offset relative to the per-CPU data area base.
+    #[inline]
+    pub fn offset(&self) -> usize {
+        self.ptr.offset()
+    }
+
+    /// Returns the raw pointer of this per-CPU data on the current CPU.
+    ///
+    /// # Safety
+    ///
+    /// Caller must ensure that preemption is disabled on the current CPU.
+    #[inline]
+    pub unsafe fn current_ptr(&self) -> *const T {
+        self.ptr.current_ptr()
+    }
+
+    /// Returns the reference of this per-CPU data on the current CPU.
+    ///
+    /// # Safety
+    ///
+    /// Caller must ensure that preemption is disabled on the current CPU.
+    #[inline]
+    pub unsafe fn current_ref_raw(&self) -> &T {
+        &*self.current_ptr()
+    }
+
+    /// Returns the mutable reference of this per-CPU data on the current CPU.
+    ///
+    /// # Safety
+    ///
+    /// Caller must ensure that preemption is disabled on the current CPU.
+    #[inline]
+    #[allow(clippy::mut_from_ref)]
+    pub unsafe fn current_ref_mut_raw(&self) -> &mut T {
+        &mut *(self.current_ptr() as *mut T)
+    }
+
+    /// Manipulate this per-CPU data on the current CPU in the given closure.
+    /// Preemption will be disabled during the call.
+    #[inline]
+    pub fn with_current<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        self.ptr.with_current(f)
+    }
+
+    /// Returns the raw pointer of this per-CPU data on the given CPU.
+    ///
+    /// # Safety
+    ///
+    /// Caller must ensure that
+    /// - the CPU ID is valid, and
+    /// - data races will not happen.
+    #[inline]
+    pub unsafe fn remote_ptr(&self, cpu_id: usize) -> *const T {
+        self.ptr.remote_ptr(cpu_id)
+    }
+
+    /// Returns the reference of this per-CPU data on the given CPU.
+    ///
+    /// # Safety
+    ///
+    /// Caller must ensure that
+    /// - the CPU ID is valid, and
+    /// - data races will not happen.
+    #[inline]
+    pub unsafe fn remote_ref_raw(&self, cpu_id: usize) -> &T {
+        &*self.remote_ptr(cpu_id)
+    }
+
+    /// Returns the mutable reference of this per-CPU data on the given CPU.
+    ///
+    /// # Safety
+    ///
+    /// Caller must ensure that
+    /// - the CPU ID is valid, and
+    /// - data races will not happen.
+    #[inline]
+    #[allow(clippy::mut_from_ref)]
+    pub unsafe fn remote_ref_mut_raw(&self, cpu_id: usize) -> &mut T {
+        &mut *(self.remote_ptr(cpu_id) as *mut T)
+    }
+}
+
+impl<T: Primitive> PerCpuBox<T> {
+    /// Returns the value of this per-CPU data on the current CPU.
+    ///
+    /// # Safety
+    ///
+    /// Caller must ensure that preemption is disabled on the current CPU.
+    #[inline]
+    pub unsafe fn read_current_raw(&self) -> T {
+        self.ptr.read_current_raw()
+    }
+
+    /// Set the value of this per-CPU data on the current CPU.
+    ///
+    /// # Safety
+    ///
+    /// Caller must ensure that preemption is disabled on the current CPU.
+    #[inline]
+    pub unsafe fn write_current_raw(&self, val: T) {
+        self.ptr.write_current_raw(val)
+    }
+
+    /// Returns the value of this per-CPU data on the current CPU. Preemption will be disabled during the call.
+    #[inline]
+    pub fn read_current(&self) -> T {
+        self.ptr.read_current()
+    }
+
+    /// Set the value of this per-CPU data on the current CPU. Preemption will be disabled during the call.
+    #[inline]
+    pub fn write_current(&self, val: T) {
+        self.ptr.write_current(val)
+    }
+}
+
+impl<T: PrimitiveInt> PerCpuBox<T> {
+    /// Adds `val` to this per-CPU data on the current CPU, wrapping around on overflow.
+    ///
+    /// # Safety
+    ///
+    /// Caller must ensure that preemption is disabled on the current CPU.
+    #[inline]
+    pub unsafe fn add_current_raw(&self, val: T) {
+        self.ptr.add_current_raw(val)
+    }
+
+    /// Adds `val` to this per-CPU data on the current CPU, wrapping around on overflow, see
+    /// [`PerCpuPtr::add_current`].
+    #[inline]
+    pub fn add_current(&self, val: T) {
+        self.ptr.add_current(val)
+    }
+}
+
+impl<T> PerCpuVar for PerCpuBox<T> {
+    type Target = T;
+
+    #[inline]
+    fn offset(&self) -> usize {
+        self.ptr.offset()
+    }
+
+    #[inline]
+    unsafe fn current_ptr(&self) -> *const T {
+        self.ptr.current_ptr()
+    }
+
+    #[inline]
+    unsafe fn remote_ptr(&self, cpu_id: usize) -> *const T {
+        self.ptr.remote_ptr(cpu_id)
+    }
+
+    #[inline]
+    fn with_current<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        self.ptr.with_current(f)
+    }
+}
+
+impl<T: Primitive> PerCpuPrimitive for PerCpuBox<T> {
+    #[inline]
+    unsafe fn read_current_raw(&self) -> T {
+        self.ptr.read_current_raw()
+    }
+
+    #[inline]
+    unsafe fn write_current_raw(&self, val: T) {
+        self.ptr.write_current_raw(val)
+    }
+
+    #[inline]
+    fn read_current(&self) -> T {
+        self.ptr.read_current()
+    }
+
+    #[inline]
+    fn write_current(&self, val: T) {
+        self.ptr.write_current(val)
+    }
+}
+
+impl<T> Drop for PerCpuBox<T> {
+    fn drop(&mut self) {
+        let offset = self.ptr.offset();
+        let mut state = STATE.lock();
+        for cpu_id in state.areas().iter() {
+            let ptr = (crate::percpu_area_base(cpu_id) + offset) as *mut T;
+            unsafe { ptr.drop_in_place() };
+        }
+        state.free(offset - DYNAMIC_CHUNK.offset(), size_of::<T>());
+    }
+}
@@ -1,11 +1,24 @@
-const fn align_up_64(val: usize) -> usize {
-    const SIZE_64BIT: usize = 0x40;
-    (val + SIZE_64BIT - 1) & !(SIZE_64BIT - 1)
+use core::alloc::Layout;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use crate::{InitError, PercpuLayout};
+
+const fn align_up(val: usize, align: usize) -> usize {
+    (val + align - 1) & !(align - 1)
 }
 
-#[cfg(not(target_os = "none"))]
+/// The base address of the contiguous per-CPU data areas, if they are
+/// allocated in the initialization rather than reserved by the linker script.
 static PERCPU_AREA_BASE: spin::once::Once<usize> = spin::once::Once::new();
 
+/// The base addresses of the per-CPU data areas, if they are given by
+/// [`init_with_bases`] instead of being contiguous.
+static PERCPU_AREA_BASES: spin::once::Once<&'static [usize]> = spin::once::Once::new();
+
+static PERCPU_AREA_NUM: AtomicUsize = AtomicUsize::new(0);
+
+static PERCPU_AREA_INITED: AtomicBool = AtomicBool::new(false);
+
 /// Returns the per-CPU data area size for one CPU.
 #[doc(cfg(not(feature = "sp-naive")))]
 pub fn percpu_area_size() -> usize {
@@ -18,42 +31,463 @@ pub fn percpu_area_size() -> usize {
     percpu_symbol_offset!(_percpu_load_end) - percpu_symbol_offset!(_percpu_load_start)
 }
 
+/// Returns the size of the data part of the per-CPU data area, i.e., excluding
+/// the zero-initialized `.percpu.bss` part at the end.
+#[cfg(target_os = "none")]
+fn percpu_area_data_size() -> usize {
+    extern "C" {
+        fn _percpu_load_start();
+        fn _percpu_bss_start();
+    }
+    use percpu_macros::percpu_symbol_offset;
+    percpu_symbol_offset!(_percpu_bss_start) - percpu_symbol_offset!(_percpu_load_start)
+}
+
+/// Returns the alignment of the per-CPU data areas, i.e., the maximum alignment
+/// of all per-CPU static variables, and at least 64 bytes (a cache line).
+#[doc(cfg(not(feature = "sp-naive")))]
+pub fn percpu_area_align() -> usize {
+    extern "C" {
+        fn _percpu_align();
+    }
+    use percpu_macros::percpu_symbol_offset;
+    percpu_symbol_offset!(_percpu_align).max(0x40)
+}
+
+/// Returns the distance between the per-CPU data areas of two adjacent CPUs.
+fn percpu_area_stride() -> usize {
+    align_up(percpu_area_size(), percpu_area_align())
+}
+
 /// Returns the base address of the per-CPU data area on the given CPU.
-///
-/// if `cpu_id` is 0, it returns the base address of all per-CPU data areas.
 #[doc(cfg(not(feature = "sp-naive")))]
 pub fn percpu_area_base(cpu_id: usize) -> usize {
+    if let Some(bases) = PERCPU_AREA_BASES.get() {
+        return bases[cpu_id];
+    }
+    cfg_if::cfg_if! {
+        if #[cfg(target_os = "none")] {
+            // The first area is the load image of the `.percpu` section, which is
+            // kept as the template of the initial values.
+            let base = match PERCPU_AREA_BASE.get() {
+                Some(base) => *base,
+                None => percpu_template_base() + percpu_area_stride(),
+            };
+        } else {
+            let base = *PERCPU_AREA_BASE.get().expect("per-CPU access before percpu::init");
+        }
+    }
+    base + cpu_id * percpu_area_stride()
+}
+
+/// Returns the base address of the load image of the `.percpu` section.
+#[cfg(target_os = "none")]
+fn percpu_template_base() -> usize {
+    extern "C" {
+        fn _percpu_start();
+    }
+    _percpu_start as *const () as usize
+}
+
+/// Returns the number of initialized per-CPU data areas, i.e., the `max_cpu_num`
+/// passed to [`init`].
+///
+/// Returns `0` if [`init`] has not been called.
+pub fn percpu_area_num() -> usize {
+    PERCPU_AREA_NUM.load(Ordering::Acquire)
+}
+
+/// Returns the maximum number of CPUs whose per-CPU data areas fit in the
+/// reserved space.
+///
+/// On bare metal, the space is reserved by the linker script, from
+/// `_percpu_start` to `_percpu_end`, and the first area is kept as the template
+/// of the initial values. On hosted Linux, the per-CPU data areas are allocated
+/// in [`init`], so it is the same as [`percpu_area_num`], as well as if they are
+/// allocated by [`init_with_allocator`]. If the per-CPU data areas are given by
+/// [`init_with_bases`], it is the number of the bases.
+pub fn percpu_area_capacity() -> usize {
+    if let Some(bases) = PERCPU_AREA_BASES.get() {
+        return bases.len();
+    }
+    if PERCPU_AREA_BASE.is_completed() {
+        return percpu_area_num();
+    }
     cfg_if::cfg_if! {
         if #[cfg(target_os = "none")] {
             extern "C" {
-                fn _percpu_start();
+                fn _percpu_end();
+            }
+            let reserved = _percpu_end as *const () as usize - percpu_template_base();
+            match reserved.checked_div(percpu_area_stride()) {
+                Some(num) => num.saturating_sub(1),
+                // No per-CPU data at all, any number of CPUs fits.
+                None => usize::MAX,
             }
-            let base = _percpu_start as usize;
         } else {
-            let base = *PERCPU_AREA_BASE.get().unwrap();
+            percpu_area_num()
         }
     }
-    base + cpu_id * align_up_64(percpu_area_size())
 }
 
 /// Initialize the per-CPU data area for `max_cpu_num` CPUs.
+///
+/// Only the first call takes effect, the subsequent calls are ignored.
+///
+/// # Panics
+///
+/// Panics if the initialization fails, see [`try_init`] for the reasons.
 pub fn init(max_cpu_num: usize) {
-    let size = percpu_area_size();
-
-    #[cfg(target_os = "linux")]
-    {
-        // we not load the percpu section in ELF, allocate them here.
-        let total_size = align_up_64(size) * max_cpu_num;
-        let layout = std::alloc::Layout::from_size_align(total_size, 0x1000).unwrap();
-        PERCPU_AREA_BASE.call_once(|| unsafe { std::alloc::alloc(layout) as usize });
-    }
-
-    let base = percpu_area_base(0);
-    for i in 1..max_cpu_num {
-        let secondary_base = percpu_area_base(i);
-        // copy per-cpu data of the primary CPU to other CPUs.
-        unsafe {
-            core::ptr::copy_nonoverlapping(base as *const u8, secondary_base as *mut u8, size);
+    match try_init(max_cpu_num) {
+        Ok(_) | Err(InitError::AlreadyInitialized) => {}
+        Err(err) => panic!("failed to initialize per-CPU data areas: {}", err),
+    }
+}
+
+/// Initialize the per-CPU data area for `max_cpu_num` CPUs, and returns the
+/// layout of the per-CPU data areas.
+///
+/// Returns an error if the per-CPU data areas have already been initialized,
+/// `max_cpu_num` is zero or exceeds [`CpuMask::CAPACITY`](crate::CpuMask::CAPACITY),
+/// the per-CPU data areas cannot be allocated, or on
+/// bare metal, `max_cpu_num` exceeds the [`percpu_area_capacity`] reserved by
+/// the linker script.
+pub fn try_init(max_cpu_num: usize) -> Result<PercpuLayout, InitError> {
+    init_layout(max_cpu_num, max_cpu_num, Areas::<NoAlloc>::Default)
+}
+
+/// Initialize the per-CPU data areas for `max_cpu_num` CPUs in the memory
+/// allocated by `alloc`, and returns the layout of the per-CPU data areas.
+///
+/// `alloc` is called once with the layout of the per-CPU data areas of all
+/// CPUs, and returns the allocated memory, or null on failure. On bare metal,
+/// the space reserved by the linker script is not used in this case, so
+/// `CPU_NUM` in the linker script can be `0`, and the number of CPUs can be
+/// determined at runtime, e.g., from the device tree or ACPI tables.
+///
+/// Returns an error if the per-CPU data areas have already been initialized,
+/// `max_cpu_num` is zero, the layout overflows, `alloc` returns null, or the
+/// returned memory is misaligned.
+///
+/// # Safety
+///
+/// Caller must ensure that the memory returned by `alloc` is valid for the
+/// given layout and not used for other purposes.
+pub unsafe fn init_with_allocator(
+    max_cpu_num: usize,
+    alloc: impl FnOnce(Layout) -> *mut u8,
+) -> Result<PercpuLayout, InitError> {
+    init_layout(max_cpu_num, max_cpu_num, Areas::Alloc(alloc))
+}
+
+/// Initialize the per-CPU data areas at the given base addresses, one for each
+/// CPU, instead of the contiguous space, and returns the layout of the per-CPU
+/// data areas. The number of CPUs is the number of the bases.
+///
+/// It allows placing the per-CPU data area of each CPU in the memory chosen at
+/// runtime, e.g., the memory of its NUMA node. Each base must be aligned to
+/// [`percpu_area_align`], and followed by at least [`percpu_area_size`] bytes
+/// of memory. The per-CPU data on the current CPU is still accessed with the
+/// thread pointer, while [`percpu_area_base`] looks up the bases.
+///
+/// Returns an error if the per-CPU data areas have already been initialized,
+/// `bases` is empty, or any base is misaligned.
+///
+/// # Safety
+///
+/// Caller must ensure that the memory at each base is valid and not used for
+/// other purposes, and the areas do not overlap.
+pub unsafe fn init_with_bases(bases: &'static [usize]) -> Result<PercpuLayout, InitError> {
+    init_layout(bases.len(), bases.len(), Areas::<NoAlloc>::Bases(bases))
+}
+
+/// Initialize the layout of the per-CPU data areas for `max_cpu_num` CPUs on
+/// the primary CPU, but only the per-CPU data area of CPU 0, and set the thread
+/// pointer to it.
+///
+/// The per-CPU data areas of other CPUs are left uninitialized, each secondary
+/// CPU initializes its own one with [`init_secondary`], so the copying is done
+/// in parallel and the memory is first touched by the CPU that owns it.
+///
+/// Returns an error for the same reasons as [`try_init`].
+pub fn init_primary(max_cpu_num: usize) -> Result<PercpuLayout, InitError> {
+    let layout = init_layout(max_cpu_num, 1, Areas::<NoAlloc>::Default)?;
+    set_local_thread_pointer(0);
+    Ok(layout)
+}
+
+/// Initialize the per-CPU data area of the secondary CPU `cpu_id` to the
+/// initial values, set the thread pointer to it, and mark the CPU online.
+///
+/// With the `dynamic` feature, the per-CPU data already allocated by
+/// `alloc_percpu` is initialized to its default value.
+///
+/// # Safety
+///
+/// Caller must ensure that it is called on the CPU `cpu_id`, before the
+/// per-CPU data on it is accessed, including by other CPUs.
+///
+/// # Panics
+///
+/// Panics if [`init_primary`] has not been called, or `cpu_id` is not less than
+/// [`percpu_area_num`].
+pub unsafe fn init_secondary(cpu_id: usize) {
+    assert!(cpu_id < percpu_area_num(), "invalid CPU ID: {}", cpu_id);
+    init_area(cpu_id);
+    set_local_thread_pointer(cpu_id);
+    crate::set_cpu_online(cpu_id, true);
+}
+
+/// Where the per-CPU data areas are placed.
+enum Areas<A> {
+    /// In the space reserved by the linker script on bare metal, or allocated
+    /// from the global allocator on hosted Linux.
+    Default,
+    /// At the given base addresses.
+    Bases(&'static [usize]),
+    /// In the memory allocated by the given allocator.
+    Alloc(A),
+}
+
+type NoAlloc = fn(Layout) -> *mut u8;
+
+/// Initialize the layout of the per-CPU data areas for `max_cpu_num` CPUs, and
+/// the per-CPU data areas of the first `init_num` CPUs.
+fn init_layout<A: FnOnce(Layout) -> *mut u8>(
+    max_cpu_num: usize,
+    init_num: usize,
+    areas: Areas<A>,
+) -> Result<PercpuLayout, InitError> {
+    if max_cpu_num == 0 {
+        return Err(InitError::ZeroCpus);
+    }
+    // The per-CPU data areas for so many CPUs do not fit in the address space.
+    if percpu_area_stride().checked_mul(max_cpu_num).is_none() {
+        return Err(InitError::ExceedsCapacity);
+    }
+    if max_cpu_num > crate::CpuMask::CAPACITY {
+        return Err(InitError::TooManyCpus);
+    }
+    if PERCPU_AREA_INITED.swap(true, Ordering::AcqRel) {
+        return Err(InitError::AlreadyInitialized);
+    }
+    let res = init_areas(max_cpu_num, init_num, areas);
+    if res.is_err() {
+        PERCPU_AREA_INITED.store(false, Ordering::Release);
+    }
+    res
+}
+
+fn init_areas<A: FnOnce(Layout) -> *mut u8>(
+    max_cpu_num: usize,
+    init_num: usize,
+    areas: Areas<A>,
+) -> Result<PercpuLayout, InitError> {
+    let (size, align, stride) = (
+        percpu_area_size(),
+        percpu_area_align(),
+        percpu_area_stride(),
+    );
+
+    let mut bases = None;
+    match areas {
+        Areas::Default => {
+            #[cfg(target_os = "none")]
+            {
+                // Do not overwrite whatever follows the space reserved by the linker script.
+                if max_cpu_num > percpu_area_capacity() {
+                    return Err(InitError::ExceedsCapacity);
+                }
+                // The per-CPU static variables are misaligned if the reserved space is.
+                if !percpu_template_base().is_multiple_of(align) {
+                    return Err(InitError::Misaligned);
+                }
+            }
+            #[cfg(target_os = "linux")]
+            {
+                // we not load the percpu section in ELF, allocate them here.
+                alloc_areas(max_cpu_num, stride, align.max(0x1000), |layout| unsafe {
+                    std::alloc::alloc(layout)
+                })?;
+            }
+        }
+        Areas::Bases(b) => {
+            if b.iter().any(|base| !base.is_multiple_of(align)) {
+                return Err(InitError::Misaligned);
+            }
+            PERCPU_AREA_BASES.call_once(|| b);
+            bases = Some(b);
+        }
+        Areas::Alloc(alloc) => alloc_areas(max_cpu_num, stride, align, alloc)?,
+    }
+
+    for i in 0..init_num {
+        // copy the initial values to the CPUs.
+        unsafe { init_area(i) };
+    }
+    crate::cpumask::init_masks(max_cpu_num, init_num);
+    PERCPU_AREA_NUM.store(max_cpu_num, Ordering::Release);
+
+    Ok(PercpuLayout {
+        base: percpu_area_base(0),
+        align,
+        stride: if bases.is_some() { 0 } else { stride },
+        area_size: size,
+        cpu_num: max_cpu_num,
+        bases,
+    })
+}
+
+/// Allocates the contiguous per-CPU data areas for `max_cpu_num` CPUs with
+/// `alloc`.
+fn alloc_areas(
+    max_cpu_num: usize,
+    stride: usize,
+    align: usize,
+    alloc: impl FnOnce(Layout) -> *mut u8,
+) -> Result<(), InitError> {
+    let layout = stride
+        .checked_mul(max_cpu_num)
+        .and_then(|total_size| Layout::from_size_align(total_size, align).ok())
+        .ok_or(InitError::ExceedsCapacity)?;
+    let base = alloc(layout);
+    if base.is_null() {
+        return Err(InitError::AllocFailed);
+    }
+    if !(base as usize).is_multiple_of(align) {
+        return Err(InitError::Misaligned);
+    }
+    PERCPU_AREA_BASE.call_once(|| base as usize);
+    Ok(())
+}
+
+/// Restores the per-CPU data area on the given CPU to the initial values of all
+/// per-CPU static variables, e.g., when the CPU is brought online again.
+///
+/// With the `dynamic` feature, the per-CPU data allocated by `alloc_percpu` is
+/// dropped, and initialized to its default value again.
+///
+/// # Safety
+///
+/// Caller must ensure that the per-CPU data on the given CPU is not being
+/// accessed, and the per-CPU static variables on it do not own any resources,
+/// which are leaked otherwise.
+///
+/// # Panics
+///
+/// Panics if `cpu_id` is not less than [`percpu_area_num`].
+#[doc(cfg(not(feature = "sp-naive")))]
+pub unsafe fn reset_cpu_area(cpu_id: usize) {
+    assert!(cpu_id < percpu_area_num(), "invalid CPU ID: {}", cpu_id);
+    init_area(cpu_id);
+    // `SELF_PTR` is read to get the thread pointer, keep it valid if the CPU is running.
+    #[cfg(target_arch = "x86_64")]
+    SELF_PTR.write_remote_raw(cpu_id, percpu_area_base(cpu_id));
+}
+
+/// Initializes the per-CPU data area on the given CPU to the initial values of
+/// all per-CPU static variables, and with the `dynamic` feature, the per-CPU
+/// data allocated by `alloc_percpu` to its default value.
+unsafe fn init_area(cpu_id: usize) {
+    let base = percpu_area_base(cpu_id);
+    let copy = || copy_initial_values(base, percpu_area_size());
+    #[cfg(feature = "dynamic")]
+    crate::dynamic::init_area(cpu_id, base, copy);
+    #[cfg(not(feature = "dynamic"))]
+    copy();
+}
+
+/// Copies the initial values of all per-CPU static variables to the per-CPU
+/// data area at `base`, whose size is `size`.
+unsafe fn copy_initial_values(base: usize, size: usize) {
+    cfg_if::cfg_if! {
+        if #[cfg(target_os = "linux")] {
+            // the `.percpu` section is not loaded, copy the initial values from the templates.
+            core::ptr::write_bytes(base as *mut u8, 0, size);
+            copy_init_templates(base);
+        } else if #[cfg(target_os = "none")] {
+            // only the data part is in the template, the `.percpu.bss` part is zeroed.
+            let data_size = percpu_area_data_size();
+            core::ptr::copy_nonoverlapping(percpu_template_base() as *const u8, base as *mut u8, data_size);
+            core::ptr::write_bytes((base + data_size) as *mut u8, 0, size - data_size);
+        } else {
+            compile_error!("per-CPU data areas are only supported on bare metal and Linux, use the `sp-naive` feature on other systems");
+        }
+    }
+}
+
+/// Copies the initial values of all per-CPU static variables, recorded in the
+/// `percpu_init` section, to the per-CPU data area at `base`.
+#[cfg(target_os = "linux")]
+unsafe fn copy_init_templates(base: usize) {
+    use crate::__priv::InitEntry;
+    extern "C" {
+        static __start_percpu_init: InitEntry;
+        static __stop_percpu_init: InitEntry;
+    }
+    let start = core::ptr::addr_of!(__start_percpu_init);
+    let end = core::ptr::addr_of!(__stop_percpu_init);
+    let num = (end as usize - start as usize) / core::mem::size_of::<InitEntry>();
+    for entry in core::slice::from_raw_parts(start, num) {
+        let dst = (base + entry.var as usize) as *mut u8;
+        core::ptr::copy_nonoverlapping(entry.template, dst, entry.size);
+    }
+}
+
+/// Ensures that the `percpu_init` section exists, even if no per-CPU static
+/// variables are defined.
+#[cfg(target_os = "linux")]
+#[used]
+#[link_section = "percpu_init"]
+static EMPTY_INIT_ENTRY: crate::__priv::InitEntry = crate::__priv::InitEntry {
+    var: core::ptr::null(),
+    template: core::ptr::null(),
+    size: 0,
+};
+
+/// Checks that the per-CPU data on the current CPU can be accessed, i.e., the
+/// per-CPU data areas are initialized, and the thread pointer is set to one of
+/// them.
+#[cfg(feature = "debug-checks")]
+pub(crate) fn check_current() {
+    let num = percpu_area_num();
+    assert!(num != 0, "per-CPU access before percpu::init");
+    let tp = read_thread_pointer();
+    if let Some(bases) = PERCPU_AREA_BASES.get() {
+        assert!(bases.contains(&tp), "thread pointer not set on this CPU");
+        return;
+    }
+    let (base, stride) = (percpu_area_base(0), percpu_area_stride());
+    let valid = match tp.checked_sub(base) {
+        Some(off) if stride == 0 => off == 0,
+        Some(off) => off.is_multiple_of(stride) && off / stride < num,
+        None => false,
+    };
+    assert!(valid, "thread pointer not set on this CPU");
+}
+
+/// Read the thread pointer without accessing the per-CPU data, which faults if
+/// the thread pointer is not set.
+#[cfg(feature = "debug-checks")]
+fn read_thread_pointer() -> usize {
+    cfg_if::cfg_if! {
+        if #[cfg(all(target_arch = "x86_64", target_os = "linux"))] {
+            const ARCH_GET_GS: u32 = 0x1004;
+            const SYS_ARCH_PRCTL: u32 = 158;
+            let mut tp: usize = 0;
+            unsafe {
+                core::arch::asm!(
+                    "syscall",
+                    inlateout("eax") SYS_ARCH_PRCTL => _,
+                    in("edi") ARCH_GET_GS,
+                    in("rsi") &mut tp as *mut usize,
+                    lateout("rcx") _,
+                    lateout("r11") _,
+                );
+            }
+            tp
+        } else {
+            get_local_thread_pointer()
         }
     }
 }
@@ -87,6 +521,22 @@ pub fn get_local_thread_pointer() -> usize {
     tp
 }
 
+/// Returns the per-CPU data area base on the current CPU, i.e., the thread
+/// pointer.
+///
+/// On x86_64, it is loaded from `gs:SELF_PTR` with a single instruction, as
+/// the generated accessors do, rather than reading `GS_BASE` with `rdmsr`.
+#[inline]
+pub(crate) fn local_area_base() -> usize {
+    cfg_if::cfg_if! {
+        if #[cfg(target_arch = "x86_64")] {
+            unsafe { SELF_PTR.read_current_raw() }
+        } else {
+            get_local_thread_pointer()
+        }
+    }
+}
+
 /// Set the architecture-specific thread pointer register to the per-CPU data
 /// area base on the current CPU.
 ///
@@ -131,5 +581,5 @@ use crate as percpu;
 /// On x86, we use `gs:SELF_PTR` to store the address of the per-CPU data area base.
 #[cfg(target_arch = "x86_64")]
 #[no_mangle]
-#[percpu_macros::def_percpu]
+#[percpu_macros::def_percpu(first)]
 static SELF_PTR: usize = 0;
@@ -0,0 +1,91 @@
+use core::fmt;
+
+/// The layout of the per-CPU data areas, returned by [`try_init`](crate::try_init).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PercpuLayout {
+    pub(crate) base: usize,
+    pub(crate) align: usize,
+    pub(crate) stride: usize,
+    pub(crate) area_size: usize,
+    pub(crate) cpu_num: usize,
+    pub(crate) bases: Option<&'static [usize]>,
+}
+
+impl PercpuLayout {
+    /// The base address of the per-CPU data area on CPU 0.
+    pub fn base(&self) -> usize {
+        self.base
+    }
+
+    /// The alignment of the per-CPU data areas, i.e., the maximum alignment of
+    /// all per-CPU static variables.
+    pub fn align(&self) -> usize {
+        self.align
+    }
+
+    /// The distance between the per-CPU data areas of two adjacent CPUs, or `0`
+    /// if the per-CPU data areas are not contiguous, i.e., placed at the bases
+    /// given by [`init_with_bases`](crate::init_with_bases).
+    pub fn stride(&self) -> usize {
+        self.stride
+    }
+
+    /// The size of the per-CPU data area for one CPU.
+    pub fn area_size(&self) -> usize {
+        self.area_size
+    }
+
+    /// The number of initialized per-CPU data areas.
+    pub fn cpu_num(&self) -> usize {
+        self.cpu_num
+    }
+
+    /// The base addresses of the per-CPU data areas, if they are given by
+    /// [`init_with_bases`](crate::init_with_bases).
+    pub fn bases(&self) -> Option<&'static [usize]> {
+        self.bases
+    }
+
+    /// The base address of the per-CPU data area on the given CPU.
+    pub fn area_base(&self, cpu_id: usize) -> usize {
+        match self.bases {
+            Some(bases) => bases[cpu_id],
+            None => self.base + cpu_id * self.stride,
+        }
+    }
+}
+
+/// The error type returned by [`try_init`](crate::try_init).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InitError {
+    /// The per-CPU data areas have already been initialized.
+    AlreadyInitialized,
+    /// The number of CPUs is zero.
+    ZeroCpus,
+    /// The number of CPUs exceeds [`CpuMask::CAPACITY`](crate::CpuMask::CAPACITY).
+    TooManyCpus,
+    /// Failed to allocate memory for the per-CPU data areas.
+    AllocFailed,
+    /// The per-CPU data areas for the given number of CPUs do not fit in the
+    /// available space, e.g., the space reserved by the linker script.
+    ExceedsCapacity,
+    /// The space reserved for the per-CPU data areas is not aligned to the
+    /// maximum alignment of all per-CPU static variables.
+    Misaligned,
+}
+
+impl fmt::Display for InitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = match self {
+            Self::AlreadyInitialized => "per-CPU data areas have already been initialized",
+            Self::ZeroCpus => "the number of CPUs is zero",
+            Self::TooManyCpus => "the number of CPUs exceeds the capacity of CPU masks",
+            Self::AllocFailed => "failed to allocate memory for per-CPU data areas",
+            Self::ExceedsCapacity => "per-CPU data areas exceed the available space",
+            Self::Misaligned => "per-CPU data areas are misaligned",
+        };
+        f.write_str(msg)
+    }
+}
+
+impl core::error::Error for InitError {}
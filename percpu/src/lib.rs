@@ -2,22 +2,134 @@
 #![feature(doc_cfg)]
 #![doc = include_str!("../README.md")]
 
+#[cfg(feature = "alloc")]
+extern crate alloc;
 extern crate percpu_macros;
 
+mod cpumask;
+#[cfg(feature = "dynamic")]
+mod dynamic;
 #[cfg_attr(feature = "sp-naive", path = "naive.rs")]
 mod imp;
+mod layout;
+mod primitive;
+mod ptr;
+mod token;
+mod var;
 
+pub use self::cpumask::{
+    cpu_online, cpu_online_mask, cpu_possible_mask, cpu_present_mask, set_cpu_online,
+    set_cpu_present, CpuMask, CpuMaskIter,
+};
+#[cfg(feature = "dynamic")]
+pub use self::dynamic::{alloc_percpu, PerCpuBox, DYNAMIC_CHUNK_SIZE};
 pub use self::imp::*;
-pub use percpu_macros::def_percpu;
+pub use self::layout::{InitError, PercpuLayout};
+pub use self::primitive::{Primitive, PrimitiveInt};
+pub use self::ptr::{PerCpuField, PerCpuPtr};
+pub use self::token::{PreemptGuard, PreemptToken};
+pub use self::var::{PerCpuPrimitive, PerCpuVar, RemoteIter};
+pub use percpu_macros::{def_percpu, percpu_field};
 
 #[doc(hidden)]
 pub mod __priv {
+    /// Disables local interrupts in the compare-exchange and exchange operations, on the targets without atomic
+    /// instructions of the size.
+    pub use kernel_guard::IrqSave as IrqSaveGuard;
     #[cfg(feature = "preempt")]
     pub use kernel_guard::NoPreempt as NoPreemptGuard;
+
+    /// Stores the per-CPU data defined with `#[def_percpu(cacheline_aligned)]`, to align and pad it to a cache line.
+    #[repr(C, align(64))]
+    pub struct CachelineAligned<T>(pub T);
+
+    /// Describes the initial value of a per-CPU static variable for the hosted Linux backend, where the `.percpu`
+    /// section is not loaded. It is placed in the `percpu_init` section.
+    #[cfg(all(target_os = "linux", not(feature = "sp-naive")))]
+    #[repr(C)]
+    pub struct InitEntry {
+        /// Address of the per-CPU static variable, i.e., its offset in the per-CPU data area.
+        pub var: *const u8,
+        /// Address of the initial value.
+        pub template: *const u8,
+        /// Size of the per-CPU static variable.
+        pub size: usize,
+    }
+
+    #[cfg(all(target_os = "linux", not(feature = "sp-naive")))]
+    unsafe impl Sync for InitEntry {}
+
+    /// Checks the per-CPU access on the current CPU, for the `debug-checks` feature.
+    #[cfg(all(feature = "debug-checks", not(feature = "sp-naive")))]
+    pub fn check_current() {
+        crate::imp::check_current()
+    }
+
+    /// Checks the initial value of a per-CPU static variable defined with `#[def_percpu(zeroed)]` at compile time.
+    pub const fn is_all_zeros(bytes: &[u8]) -> bool {
+        let mut i = 0;
+        while i < bytes.len() {
+            if bytes[i] != 0 {
+                return false;
+            }
+            i += 1;
+        }
+        true
+    }
+
+    /// Probes whether the per-CPU data projected by `percpu_field!` implements `Deref`, so that the projection never
+    /// dereferences a pointer (e.g., a reference or a `Box`) through auto-deref.
+    ///
+    /// `(&FieldProbe::of(ptr)).check()` resolves to [`DerefCheck::check`] returning [`DerefField`] if the data
+    /// implements `Deref`, or to [`NotDerefCheck::check`] returning [`NotDeref`] otherwise.
+    pub struct FieldProbe<T: ?Sized>(core::marker::PhantomData<*const T>);
+
+    impl<T: ?Sized> FieldProbe<T> {
+        pub fn of(_ptr: *const T) -> Self {
+            Self(core::marker::PhantomData)
+        }
+    }
+
+    /// The data whose field is projected by `percpu_field!` implements `Deref`.
+    pub struct DerefField;
+
+    /// The data whose field is projected by `percpu_field!` does not implement `Deref`.
+    pub struct NotDeref;
+
+    pub trait DerefCheck {
+        fn check(&self) -> DerefField {
+            DerefField
+        }
+    }
+
+    impl<T: ?Sized + core::ops::Deref> DerefCheck for FieldProbe<T> {}
+
+    pub trait NotDerefCheck {
+        fn check(&self) -> NotDeref {
+            NotDeref
+        }
+    }
+
+    impl<T: ?Sized> NotDerefCheck for &FieldProbe<T> {}
+
+    /// Checks the per-CPU access on the given CPU, for the `debug-checks` feature.
+    #[cfg(feature = "debug-checks")]
+    pub fn check_remote(cpu_id: usize) {
+        let num = crate::percpu_area_num();
+        assert!(num != 0, "per-CPU access before percpu::init");
+        assert!(
+            cpu_id < num,
+            "invalid CPU ID {}: only {} per-CPU data areas are initialized",
+            cpu_id,
+            num
+        );
+    }
 }
 
 cfg_if::cfg_if! {
     if #[cfg(doc)] {
+        use crate as percpu;
+
         /// Example per-CPU data for documentation only.
         #[doc(cfg(doc))]
         #[def_percpu]
@@ -1,11 +1,103 @@
-/// No effect for "sp-naive" use.
-pub fn init(_max_cpu_num: usize) {}
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{InitError, PercpuLayout};
+
+static PERCPU_AREA_NUM: AtomicUsize = AtomicUsize::new(0);
+
+/// Only records `max_cpu_num` for "sp-naive" use.
+///
+/// Only the first call takes effect, the subsequent calls are ignored.
+///
+/// # Panics
+///
+/// Panics if `max_cpu_num` is zero or exceeds [`CpuMask::CAPACITY`](crate::CpuMask::CAPACITY).
+pub fn init(max_cpu_num: usize) {
+    match try_init(max_cpu_num) {
+        Ok(_) | Err(InitError::AlreadyInitialized) => {}
+        Err(err) => panic!("failed to initialize per-CPU data areas: {}", err),
+    }
+}
+
+/// Only records `max_cpu_num` for "sp-naive" use. The returned layout has zero
+/// base and stride, as all CPUs share the same global data.
+///
+/// Returns an error if it has already been called, or `max_cpu_num` is zero or
+/// exceeds [`CpuMask::CAPACITY`](crate::CpuMask::CAPACITY).
+pub fn try_init(max_cpu_num: usize) -> Result<PercpuLayout, InitError> {
+    init_layout(max_cpu_num, max_cpu_num)
+}
+
+/// Records `max_cpu_num`, of which the first `online_num` CPUs are online.
+fn init_layout(max_cpu_num: usize, online_num: usize) -> Result<PercpuLayout, InitError> {
+    if max_cpu_num == 0 {
+        return Err(InitError::ZeroCpus);
+    }
+    if max_cpu_num > crate::CpuMask::CAPACITY {
+        return Err(InitError::TooManyCpus);
+    }
+    PERCPU_AREA_NUM
+        .compare_exchange(0, max_cpu_num, Ordering::AcqRel, Ordering::Acquire)
+        .map_err(|_| InitError::AlreadyInitialized)?;
+    crate::cpumask::init_masks(max_cpu_num, online_num);
+    Ok(PercpuLayout {
+        base: 0,
+        align: 1,
+        stride: 0,
+        area_size: 0,
+        cpu_num: max_cpu_num,
+        bases: None,
+    })
+}
+
+/// Same as [`try_init`] for "sp-naive" use, `alloc` is not called.
+///
+/// # Safety
+///
+/// Always safe for "sp-naive" use, it is `unsafe` only to be consistent with
+/// other backends.
+pub unsafe fn init_with_allocator(
+    max_cpu_num: usize,
+    _alloc: impl FnOnce(core::alloc::Layout) -> *mut u8,
+) -> Result<PercpuLayout, InitError> {
+    try_init(max_cpu_num)
+}
+
+/// Same as [`try_init`] with the number of the bases for "sp-naive" use, the
+/// bases are not used.
+///
+/// # Safety
+///
+/// Always safe for "sp-naive" use, it is `unsafe` only to be consistent with
+/// other backends.
+pub unsafe fn init_with_bases(bases: &'static [usize]) -> Result<PercpuLayout, InitError> {
+    try_init(bases.len())
+}
+
+/// Same as [`try_init`] for "sp-naive" use, but only CPU 0 is online.
+pub fn init_primary(max_cpu_num: usize) -> Result<PercpuLayout, InitError> {
+    init_layout(max_cpu_num, 1)
+}
+
+/// Only marks the CPU online for "sp-naive" use.
+///
+/// # Safety
+///
+/// Always safe for "sp-naive" use, it is `unsafe` only to be consistent with
+/// other backends.
+pub unsafe fn init_secondary(cpu_id: usize) {
+    crate::set_cpu_online(cpu_id, true);
+}
 
 /// Always returns `0` for "sp-naive" use.
 pub fn get_local_thread_pointer() -> usize {
     0
 }
 
+/// Always returns `0` for "sp-naive" use.
+pub(crate) fn local_area_base() -> usize {
+    0
+}
+
 /// No effect for "sp-naive" use.
 pub fn set_local_thread_pointer(_cpu_id: usize) {}
 
@@ -14,3 +106,15 @@ pub fn set_local_thread_pointer(_cpu_id: usize) {}
 pub fn percpu_area_base(_cpu_id: usize) -> usize {
     0
 }
+
+/// Always returns `usize::MAX` for "sp-naive" use, as all CPUs share the same
+/// global data.
+pub fn percpu_area_capacity() -> usize {
+    usize::MAX
+}
+
+/// Returns the `max_cpu_num` passed to [`init`] for "sp-naive" use, as all CPUs
+/// share the same global data.
+pub fn percpu_area_num() -> usize {
+    PERCPU_AREA_NUM.load(Ordering::Acquire)
+}
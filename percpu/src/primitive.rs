@@ -0,0 +1,192 @@
+//! Primitive types that can be accessed on the current CPU with a single instruction, given their offsets relative to
+//! the per-CPU data area base.
+
+use core::ptr::NonNull;
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+/// Types that can be read or written on the current CPU with a single instruction, given their offsets relative to the
+/// per-CPU data area base.
+///
+/// It is implemented for `bool`, primitive integer types, thin raw pointers and `Option<NonNull<T>>`, i.e., the same
+/// types that have the fast `read_current` and `write_current` methods when defined with [`def_percpu`].
+///
+/// [`def_percpu`]: crate::def_percpu
+pub trait Primitive: Copy + sealed::Sealed {
+    /// Returns the value at `offset` in the per-CPU data area on the current CPU.
+    ///
+    /// # Safety
+    ///
+    /// Caller must ensure that
+    /// - preemption is disabled on the current CPU, and
+    /// - a valid value of this type is located at `offset` in the per-CPU data area.
+    unsafe fn read_current_raw(offset: usize) -> Self;
+
+    /// Set the value at `offset` in the per-CPU data area on the current CPU.
+    ///
+    /// # Safety
+    ///
+    /// Caller must ensure that
+    /// - preemption is disabled on the current CPU, and
+    /// - a valid value of this type is located at `offset` in the per-CPU data area.
+    unsafe fn write_current_raw(offset: usize, val: Self);
+}
+
+/// Primitive integer types that can be updated on the current CPU with a single instruction, given their offsets
+/// relative to the per-CPU data area base.
+pub trait PrimitiveInt: Primitive + Default {
+    /// Adds `rhs` to `self`, wrapping around on overflow.
+    fn wrapping_add(self, rhs: Self) -> Self;
+
+    /// Adds `val` to the value at `offset` in the per-CPU data area on the current CPU, wrapping around on overflow.
+    ///
+    /// # Safety
+    ///
+    /// Caller must ensure that
+    /// - preemption is disabled on the current CPU, and
+    /// - a valid value of this type is located at `offset` in the per-CPU data area.
+    unsafe fn add_current_raw(offset: usize, val: Self);
+}
+
+/// Returns the pointer to the value at `offset` in the per-CPU data area on the current CPU.
+#[allow(dead_code)]
+#[inline]
+fn current_ptr<T>(offset: usize) -> *mut T {
+    (crate::imp::local_area_base() + offset) as *mut T
+}
+
+macro_rules! impl_primitive_int {
+    ($($ty:ty => $ptr:literal, $reg:ident, $mod:literal;)*) => { $(
+        impl sealed::Sealed for $ty {}
+
+        impl Primitive for $ty {
+            #[inline]
+            unsafe fn read_current_raw(offset: usize) -> Self {
+                cfg_if::cfg_if! {
+                    if #[cfg(all(target_arch = "x86_64", not(target_os = "macos"), not(feature = "sp-naive")))] {
+                        let value: Self;
+                        core::arch::asm!(
+                            concat!("mov {0", $mod, "}, ", $ptr, " ptr gs:[{1}]"),
+                            out($reg) value,
+                            in(reg) offset,
+                        );
+                        value
+                    } else {
+                        *current_ptr::<Self>(offset)
+                    }
+                }
+            }
+
+            #[inline]
+            unsafe fn write_current_raw(offset: usize, val: Self) {
+                cfg_if::cfg_if! {
+                    if #[cfg(all(target_arch = "x86_64", not(target_os = "macos"), not(feature = "sp-naive")))] {
+                        core::arch::asm!(
+                            concat!("mov ", $ptr, " ptr gs:[{1}], {0", $mod, "}"),
+                            in($reg) val,
+                            in(reg) offset,
+                        );
+                    } else {
+                        *current_ptr::<Self>(offset) = val;
+                    }
+                }
+            }
+        }
+
+        impl PrimitiveInt for $ty {
+            #[inline]
+            fn wrapping_add(self, rhs: Self) -> Self {
+                <$ty>::wrapping_add(self, rhs)
+            }
+
+            #[inline]
+            unsafe fn add_current_raw(offset: usize, val: Self) {
+                cfg_if::cfg_if! {
+                    if #[cfg(all(target_arch = "x86_64", not(target_os = "macos"), not(feature = "sp-naive")))] {
+                        core::arch::asm!(
+                            concat!("add ", $ptr, " ptr gs:[{1}], {0", $mod, "}"),
+                            in($reg) val,
+                            in(reg) offset,
+                        );
+                    } else {
+                        let ptr = current_ptr::<Self>(offset);
+                        *ptr = (*ptr).wrapping_add(val);
+                    }
+                }
+            }
+        }
+    )* };
+}
+
+impl_primitive_int! {
+    u8 => "byte", reg_byte, "";
+    u16 => "word", reg, ":x";
+    u32 => "dword", reg, ":e";
+    u64 => "qword", reg, ":r";
+    usize => "qword", reg, ":r";
+    i8 => "byte", reg_byte, "";
+    i16 => "word", reg, ":x";
+    i32 => "dword", reg, ":e";
+    i64 => "qword", reg, ":r";
+    isize => "qword", reg, ":r";
+}
+
+impl sealed::Sealed for bool {}
+
+impl Primitive for bool {
+    #[inline]
+    unsafe fn read_current_raw(offset: usize) -> Self {
+        u8::read_current_raw(offset) != 0
+    }
+
+    #[inline]
+    unsafe fn write_current_raw(offset: usize, val: Self) {
+        u8::write_current_raw(offset, val as u8)
+    }
+}
+
+// Pointer-like types are accessed as `usize`.
+
+impl<T> sealed::Sealed for *const T {}
+
+impl<T> Primitive for *const T {
+    #[inline]
+    unsafe fn read_current_raw(offset: usize) -> Self {
+        usize::read_current_raw(offset) as Self
+    }
+
+    #[inline]
+    unsafe fn write_current_raw(offset: usize, val: Self) {
+        usize::write_current_raw(offset, val as usize)
+    }
+}
+
+impl<T> sealed::Sealed for *mut T {}
+
+impl<T> Primitive for *mut T {
+    #[inline]
+    unsafe fn read_current_raw(offset: usize) -> Self {
+        usize::read_current_raw(offset) as Self
+    }
+
+    #[inline]
+    unsafe fn write_current_raw(offset: usize, val: Self) {
+        usize::write_current_raw(offset, val as usize)
+    }
+}
+
+impl<T> sealed::Sealed for Option<NonNull<T>> {}
+
+impl<T> Primitive for Option<NonNull<T>> {
+    #[inline]
+    unsafe fn read_current_raw(offset: usize) -> Self {
+        NonNull::new(usize::read_current_raw(offset) as *mut T)
+    }
+
+    #[inline]
+    unsafe fn write_current_raw(offset: usize, val: Self) {
+        usize::write_current_raw(offset, val.map_or(0, |p| p.as_ptr() as usize))
+    }
+}
@@ -0,0 +1,274 @@
+use core::fmt;
+use core::marker::PhantomData;
+
+use crate::{PerCpuPrimitive, PerCpuVar, Primitive, PrimitiveInt};
+
+/// A typed pointer to per-CPU data, which only holds the offset relative to the per-CPU data area base.
+///
+/// Unlike the wrapper of each per-CPU static variable, which is a unique type, it can be stored in structs or passed
+/// to generic functions. It is converted from a per-CPU static variable defined with [`def_percpu`] with `From`, or
+/// obtained by the [`percpu_field!`] macro for a field of a per-CPU static variable.
+///
+/// Like per-CPU static variables, per-CPU data of primitive types (see [`Primitive`]) can be read or written on the
+/// current CPU with a single instruction.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use percpu::PerCpuPtr;
+///
+/// #[percpu::def_percpu]
+/// static RX_PACKETS: u64 = 0;
+///
+/// #[percpu::def_percpu]
+/// static TX_PACKETS: u64 = 0;
+///
+/// let counters: [PerCpuPtr<u64>; 2] = [(&RX_PACKETS).into(), (&TX_PACKETS).into()];
+/// for counter in counters {
+///     counter.add_current(1);
+/// }
+/// assert_eq!(RX_PACKETS.read_current(), 1);
+/// ```
+///
+/// [`def_percpu`]: crate::def_percpu
+/// [`percpu_field!`]: crate::percpu_field
+pub struct PerCpuPtr<T> {
+    offset: usize,
+    _phantom: PhantomData<fn() -> T>,
+}
+
+/// A field of a per-CPU static variable, which is obtained by the [`percpu_field!`] macro.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// struct Stats {
+///     rx: u64,
+///     tx: u64,
+/// }
+///
+/// #[percpu::def_percpu]
+/// static STATS: Stats = Stats { rx: 0, tx: 0 };
+///
+/// let rx = percpu::percpu_field!(STATS.rx);
+/// rx.add_current(1);
+/// assert_eq!(rx.read_current(), 1);
+/// ```
+///
+/// [`percpu_field!`]: crate::percpu_field
+pub type PerCpuField<T> = PerCpuPtr<T>;
+
+impl<T> Clone for PerCpuPtr<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for PerCpuPtr<T> {}
+
+impl<T> fmt::Debug for PerCpuPtr<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PerCpuPtr")
+            .field("offset", &self.offset)
+            .finish()
+    }
+}
+
+impl<T> PerCpuPtr<T> {
+    /// Creates a pointer to the per-CPU data at `offset` relative to the per-CPU data area base.
+    ///
+    /// # Safety
+    ///
+    /// Caller must ensure that the per-CPU data of type `T` is located at `offset`.
+    #[inline]
+    pub unsafe fn from_offset(offset: usize) -> Self {
+        Self {
+            offset,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Creates a pointer to a field of the per-CPU data `var`, whose address is obtained by `project` from the address
+    /// of `var`. It is used by `percpu_field!`, where the type of `var` is inferred rather than named.
+    ///
+    /// # Safety
+    ///
+    /// Caller must ensure that `project` only projects the pointer to a field of `V::Target`, without dereferencing
+    /// any pointers.
+    #[doc(hidden)]
+    #[inline]
+    pub unsafe fn __field<V: PerCpuVar>(
+        var: &V,
+        project: impl FnOnce(*const V::Target) -> *const T,
+    ) -> Self {
+        // The projection needs a pointer into an allocation of `V::Target`, which is never read.
+        let data = core::mem::MaybeUninit::<V::Target>::uninit();
+        let base = data.as_ptr();
+        Self::from_offset(var.offset() + (project(base) as usize - base as usize))
+    }
+
+    /// Returns the offset relative to the per-CPU data area base.
+    #[inline]
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Returns the raw pointer of this per-CPU data on the current CPU.
+    ///
+    /// # Safety
+    ///
+    /// Caller must ensure that preemption is disabled on the current CPU.
+    #[inline]
+    pub unsafe fn current_ptr(&self) -> *const T {
+        #[cfg(all(feature = "debug-checks", not(feature = "sp-naive")))]
+        crate::__priv::check_current();
+        (crate::imp::local_area_base() + self.offset) as *const T
+    }
+
+    /// Manipulate this per-CPU data on the current CPU in the given closure.
+    /// Preemption will be disabled during the call.
+    pub fn with_current<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        #[cfg(feature = "preempt")]
+        let _guard = crate::__priv::NoPreemptGuard::new();
+        f(unsafe { &mut *(self.current_ptr() as *mut T) })
+    }
+
+    /// Returns the raw pointer of this per-CPU data on the given CPU.
+    ///
+    /// # Safety
+    ///
+    /// Caller must ensure that
+    /// - the CPU ID is valid, and
+    /// - data races will not happen.
+    #[inline]
+    pub unsafe fn remote_ptr(&self, cpu_id: usize) -> *const T {
+        #[cfg(feature = "debug-checks")]
+        crate::__priv::check_remote(cpu_id);
+        debug_assert!(
+            cpu_id < crate::percpu_area_capacity(),
+            "CPU ID {} exceeds the per-CPU area capacity {}",
+            cpu_id,
+            crate::percpu_area_capacity(),
+        );
+        (crate::percpu_area_base(cpu_id) + self.offset) as *const T
+    }
+}
+
+impl<T: Primitive> PerCpuPtr<T> {
+    /// Returns the value of this per-CPU data on the current CPU.
+    ///
+    /// # Safety
+    ///
+    /// Caller must ensure that preemption is disabled on the current CPU.
+    #[inline]
+    pub unsafe fn read_current_raw(&self) -> T {
+        #[cfg(all(feature = "debug-checks", not(feature = "sp-naive")))]
+        crate::__priv::check_current();
+        T::read_current_raw(self.offset)
+    }
+
+    /// Set the value of this per-CPU data on the current CPU.
+    ///
+    /// # Safety
+    ///
+    /// Caller must ensure that preemption is disabled on the current CPU.
+    #[inline]
+    pub unsafe fn write_current_raw(&self, val: T) {
+        #[cfg(all(feature = "debug-checks", not(feature = "sp-naive")))]
+        crate::__priv::check_current();
+        T::write_current_raw(self.offset, val)
+    }
+
+    /// Returns the value of this per-CPU data on the current CPU. Preemption will be disabled during the call.
+    pub fn read_current(&self) -> T {
+        #[cfg(feature = "preempt")]
+        let _guard = crate::__priv::NoPreemptGuard::new();
+        unsafe { self.read_current_raw() }
+    }
+
+    /// Set the value of this per-CPU data on the current CPU. Preemption will be disabled during the call.
+    pub fn write_current(&self, val: T) {
+        #[cfg(feature = "preempt")]
+        let _guard = crate::__priv::NoPreemptGuard::new();
+        unsafe { self.write_current_raw(val) }
+    }
+}
+
+impl<T: PrimitiveInt> PerCpuPtr<T> {
+    /// Adds `val` to this per-CPU data on the current CPU, wrapping around on overflow.
+    ///
+    /// # Safety
+    ///
+    /// Caller must ensure that preemption is disabled on the current CPU.
+    #[inline]
+    pub unsafe fn add_current_raw(&self, val: T) {
+        #[cfg(all(feature = "debug-checks", not(feature = "sp-naive")))]
+        crate::__priv::check_current();
+        T::add_current_raw(self.offset, val)
+    }
+
+    /// Adds `val` to this per-CPU data on the current CPU, wrapping around on overflow. On x86_64, this is a single
+    /// instruction that can be neither preempted nor interrupted. On other architectures, preemption will be disabled
+    /// during the call.
+    #[inline]
+    pub fn add_current(&self, val: T) {
+        #[cfg(all(
+            feature = "preempt",
+            any(not(target_arch = "x86_64"), feature = "sp-naive")
+        ))]
+        let _guard = crate::__priv::NoPreemptGuard::new();
+        unsafe { self.add_current_raw(val) }
+    }
+}
+
+impl<T> PerCpuVar for PerCpuPtr<T> {
+    type Target = T;
+
+    #[inline]
+    fn offset(&self) -> usize {
+        Self::offset(self)
+    }
+
+    #[inline]
+    unsafe fn current_ptr(&self) -> *const T {
+        Self::current_ptr(self)
+    }
+
+    #[inline]
+    unsafe fn remote_ptr(&self, cpu_id: usize) -> *const T {
+        Self::remote_ptr(self, cpu_id)
+    }
+
+    #[inline]
+    fn with_current<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut T) -> R,
+    {
+        Self::with_current(self, f)
+    }
+}
+
+impl<T: Primitive> PerCpuPrimitive for PerCpuPtr<T> {
+    #[inline]
+    unsafe fn read_current_raw(&self) -> T {
+        Self::read_current_raw(self)
+    }
+
+    #[inline]
+    unsafe fn write_current_raw(&self, val: T) {
+        Self::write_current_raw(self, val)
+    }
+
+    #[inline]
+    fn read_current(&self) -> T {
+        Self::read_current(self)
+    }
+
+    #[inline]
+    fn write_current(&self, val: T) {
+        Self::write_current(self, val)
+    }
+}
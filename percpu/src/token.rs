@@ -0,0 +1,67 @@
+use core::marker::PhantomData;
+
+/// A guard that disables preemption on the current CPU until it is dropped.
+///
+/// Per-CPU data on the current CPU can be referenced with the [`PreemptToken`]
+/// obtained from the guard, so that one guard covers the accesses to many
+/// per-CPU static variables.
+///
+/// Preemption is not actually disabled if the `preempt` feature is not enabled.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// #[percpu::def_percpu]
+/// static COUNTER: usize = 0;
+///
+/// #[percpu::def_percpu]
+/// static LIMIT: usize = 0;
+///
+/// let mut guard = percpu::PreemptGuard::new();
+/// let token = guard.token();
+/// // `LIMIT` and `COUNTER` are not accessed otherwise while the references are alive.
+/// let limit = unsafe { *LIMIT.current_ref(&token) };
+/// let counter = unsafe { COUNTER.current_mut(&token) };
+/// *counter = (*counter + 1).min(limit);
+/// ```
+pub struct PreemptGuard {
+    #[cfg(feature = "preempt")]
+    _guard: crate::__priv::NoPreemptGuard,
+    // The guard must be dropped on the CPU where it is created.
+    _not_send: PhantomData<*const ()>,
+}
+
+/// A token that proves preemption is disabled on the current CPU, i.e., the
+/// current CPU does not change while the token is alive.
+///
+/// It is obtained from [`PreemptGuard::token`], and is required by the
+/// `current_ref` and `current_mut` methods of per-CPU static variables, which
+/// then only need the caller to ensure that the references do not alias.
+pub struct PreemptToken<'a> {
+    _guard: PhantomData<&'a mut PreemptGuard>,
+}
+
+impl PreemptGuard {
+    /// Disables preemption on the current CPU.
+    pub fn new() -> Self {
+        Self {
+            #[cfg(feature = "preempt")]
+            _guard: crate::__priv::NoPreemptGuard::new(),
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Returns a token that proves preemption is disabled while the guard is
+    /// alive.
+    pub fn token(&mut self) -> PreemptToken<'_> {
+        PreemptToken {
+            _guard: PhantomData,
+        }
+    }
+}
+
+impl Default for PreemptGuard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,208 @@
+//! Common interface of per-CPU variables.
+
+use core::cmp::Ordering;
+
+use crate::{CpuMask, CpuMaskIter, Primitive, PrimitiveInt};
+
+/// Returns the number of distinct per-CPU data areas, i.e., the CPUs to iterate over.
+pub(crate) fn area_num() -> usize {
+    if cfg!(feature = "sp-naive") {
+        // All CPUs share the same global data.
+        crate::percpu_area_num().min(1)
+    } else {
+        crate::percpu_area_num()
+    }
+}
+
+/// Returns the CPUs visited by the remote iteration and aggregation, i.e., the online CPUs.
+fn remote_cpus() -> CpuMaskIter {
+    if cfg!(feature = "sp-naive") {
+        // The global data is only visited once.
+        CpuMask::first(area_num()).iter()
+    } else {
+        crate::cpu_online_mask().iter()
+    }
+}
+
+/// Common interface of per-CPU variables, to write generic code that accepts any of them.
+///
+/// It is implemented by the wrapper of each per-CPU static variable defined with [`def_percpu`], [`PerCpuPtr`], and
+/// `PerCpuBox` with the `dynamic` feature.
+/// The methods are the same as the inherent methods with the same names.
+///
+/// # Examples
+///
+/// ```rust,no_run
+/// use percpu::PerCpuVar;
+///
+/// fn reset_on_all_cpus<V: PerCpuVar>(var: &V, cpu_num: usize)
+/// where
+///     V::Target: Default,
+/// {
+///     for cpu_id in 0..cpu_num {
+///         unsafe { *(var.remote_ptr(cpu_id) as *mut V::Target) = Default::default() };
+///     }
+/// }
+///
+/// #[percpu::def_percpu]
+/// static COUNTER: usize = 0;
+///
+/// reset_on_all_cpus(&COUNTER, 4);
+/// ```
+///
+/// [`def_percpu`]: crate::def_percpu
+/// [`PerCpuPtr`]: crate::PerCpuPtr
+pub trait PerCpuVar {
+    /// The type of the per-CPU data.
+    type Target;
+
+    /// Returns the offset relative to the per-CPU data area base.
+    fn offset(&self) -> usize;
+
+    /// Returns the raw pointer of the per-CPU data on the current CPU.
+    ///
+    /// # Safety
+    ///
+    /// Caller must ensure that preemption is disabled on the current CPU.
+    unsafe fn current_ptr(&self) -> *const Self::Target;
+
+    /// Returns the raw pointer of the per-CPU data on the given CPU.
+    ///
+    /// # Safety
+    ///
+    /// Caller must ensure that
+    /// - the CPU ID is valid, and
+    /// - data races will not happen.
+    unsafe fn remote_ptr(&self, cpu_id: usize) -> *const Self::Target;
+
+    /// Manipulate the per-CPU data on the current CPU in the given closure.
+    /// Preemption will be disabled during the call.
+    fn with_current<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut Self::Target) -> R;
+
+    /// Returns an iterator over the per-CPU data on all online CPUs (see [`cpu_online_mask`]), in the order of the CPU
+    /// IDs, which yields the CPU ID and the reference of the per-CPU data on it. For "sp-naive" use, the global data
+    /// is only yielded once, with CPU ID `0`.
+    ///
+    /// [`cpu_online_mask`]: crate::cpu_online_mask
+    ///
+    /// # Safety
+    ///
+    /// Caller must ensure that the per-CPU data is not modified while the references are alive.
+    unsafe fn iter_remote(&self) -> RemoteIter<'_, Self>
+    where
+        Self: Sized,
+    {
+        RemoteIter {
+            var: self,
+            cpus: remote_cpus(),
+        }
+    }
+    /// Folds the values of the per-CPU data on all online CPUs, in the order of the CPU IDs, see
+    /// [`iter_remote`](Self::iter_remote) for the CPUs.
+    ///
+    /// The values are read with volatile semantics, so they are never cached by the compiler. Only the values of
+    /// primitive types (see [`Primitive`]) are read with a single instruction, others may be torn if they are being
+    /// modified on their CPUs, e.g., with some fields updated and the others not yet.
+    fn fold<B, F>(&self, init: B, mut f: F) -> B
+    where
+        Self: Sized,
+        Self::Target: Copy,
+        F: FnMut(B, Self::Target) -> B,
+    {
+        remote_cpus().fold(init, |acc, cpu_id| {
+            f(acc, unsafe { self.remote_ptr(cpu_id).read_volatile() })
+        })
+    }
+
+    /// Returns the CPU ID and the value of the per-CPU data with the maximum value on all online CPUs with respect to
+    /// `compare`, or `None` if no CPU is online. If several CPUs have the maximum value, the last one is returned.
+    ///
+    /// The values are read in the same way as [`fold`](Self::fold).
+    fn max_by<F>(&self, mut compare: F) -> Option<(usize, Self::Target)>
+    where
+        Self: Sized,
+        Self::Target: Copy,
+        F: FnMut(&Self::Target, &Self::Target) -> Ordering,
+    {
+        remote_cpus()
+            .map(|cpu_id| (cpu_id, unsafe { self.remote_ptr(cpu_id).read_volatile() }))
+            .max_by(|(_, a), (_, b)| compare(a, b))
+    }
+
+    /// Returns the values of the per-CPU data on all online CPUs, in the order of the CPU IDs.
+    ///
+    /// The values are read in the same way as [`fold`](Self::fold).
+    #[cfg(feature = "alloc")]
+    #[doc(cfg(feature = "alloc"))]
+    fn snapshot(&self) -> alloc::vec::Vec<Self::Target>
+    where
+        Self: Sized,
+        Self::Target: Copy,
+    {
+        remote_cpus()
+            .map(|cpu_id| unsafe { self.remote_ptr(cpu_id).read_volatile() })
+            .collect()
+    }
+}
+
+/// An iterator over the per-CPU data on all online CPUs, returned by [`PerCpuVar::iter_remote`].
+pub struct RemoteIter<'a, V> {
+    var: &'a V,
+    cpus: CpuMaskIter,
+}
+
+impl<'a, V: PerCpuVar> Iterator for RemoteIter<'a, V> {
+    type Item = (usize, &'a V::Target);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let cpu_id = self.cpus.next()?;
+        Some((cpu_id, unsafe { &*self.var.remote_ptr(cpu_id) }))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.cpus.size_hint()
+    }
+}
+
+impl<V: PerCpuVar> ExactSizeIterator for RemoteIter<'_, V> {}
+
+/// Per-CPU variables of primitive types (see [`Primitive`]), which can be read or written on the current CPU with a
+/// single instruction.
+pub trait PerCpuPrimitive: PerCpuVar<Target: Primitive> {
+    /// Returns the value of the per-CPU data on the current CPU.
+    ///
+    /// # Safety
+    ///
+    /// Caller must ensure that preemption is disabled on the current CPU.
+    unsafe fn read_current_raw(&self) -> Self::Target;
+
+    /// Set the value of the per-CPU data on the current CPU.
+    ///
+    /// # Safety
+    ///
+    /// Caller must ensure that preemption is disabled on the current CPU.
+    unsafe fn write_current_raw(&self, val: Self::Target);
+
+    /// Returns the value of the per-CPU data on the current CPU. Preemption will be disabled during the call.
+    fn read_current(&self) -> Self::Target;
+
+    /// Set the value of the per-CPU data on the current CPU. Preemption will be disabled during the call.
+    fn write_current(&self, val: Self::Target);
+
+    /// Returns the sum of the values of the per-CPU data on all online CPUs, wrapping around on overflow, in the same
+    /// way as [`add_current`]. E.g., a counter incremented on one CPU and decremented on another sums to `0`, though
+    /// the latter value has wrapped around.
+    ///
+    /// The values are read with volatile semantics, so they are never cached by the compiler.
+    ///
+    /// [`add_current`]: crate::PerCpuPtr::add_current
+    fn sum(&self) -> Self::Target
+    where
+        Self: Sized,
+        Self::Target: PrimitiveInt,
+    {
+        self.fold(Default::default(), PrimitiveInt::wrapping_add)
+    }
+}
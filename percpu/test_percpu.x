@@ -2,14 +2,25 @@ CPU_NUM = 4;
 
 SECTIONS
 {
-    . = ALIGN(4K);
+    . = ALIGN(MAX(4K, MAX(ALIGNOF(.percpu), ALIGNOF(.percpu.bss))));
     _percpu_start = .;
     .percpu 0x0 (NOLOAD) : AT(_percpu_start) {
         _percpu_load_start = .;
-        *(.percpu .percpu.*)
+        *(.percpu.first)
+        . = ALIGN(64);
+        *(.percpu.cacheline_aligned)
+        . = ALIGN(64);
+        *(.percpu.read_mostly)
+        . = ALIGN(64);
+        *(.percpu)
+    }
+    .percpu.bss (NOLOAD) : {
+        _percpu_bss_start = .;
+        *(.percpu.bss)
         _percpu_load_end = .;
-        . = _percpu_load_start + ALIGN(64) * CPU_NUM;
     }
-    . = _percpu_start + SIZEOF(.percpu);
+    _percpu_align = MAX(ALIGNOF(.percpu), ALIGNOF(.percpu.bss));
+    . = _percpu_start + ALIGN(_percpu_load_end - _percpu_load_start, MAX(64, _percpu_align)) * (CPU_NUM + 1);
+    _percpu_end = .;
 }
 INSERT AFTER .bss;
@@ -0,0 +1,55 @@
+#![cfg(all(not(target_os = "macos"), not(feature = "sp-naive")))]
+
+use std::alloc::Layout;
+
+use percpu::*;
+
+#[def_percpu]
+static VALUE: u64 = 0x1234_5678;
+
+#[def_percpu]
+static COUNT: usize = 0;
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_alloc() {
+    let res = unsafe { init_with_allocator(2, |_| core::ptr::null_mut()) };
+    assert_eq!(res, Err(InitError::AllocFailed));
+    let res = unsafe { init_with_allocator(2, |layout| std::alloc::alloc(layout).add(8)) };
+    assert_eq!(res, Err(InitError::Misaligned));
+    let res = unsafe { init_with_allocator(usize::MAX, |_| unreachable!()) };
+    assert_eq!(res, Err(InitError::ExceedsCapacity));
+    let res = unsafe { init_with_allocator(CpuMask::CAPACITY + 1, |_| unreachable!()) };
+    assert_eq!(res, Err(InitError::TooManyCpus));
+    let res = unsafe { init_with_allocator(0, |_| unreachable!()) };
+    assert_eq!(res, Err(InitError::ZeroCpus));
+
+    let mut requested = None;
+    let layout = unsafe {
+        init_with_allocator(3, |layout: Layout| {
+            requested = Some(layout);
+            std::alloc::alloc(layout)
+        })
+    }
+    .unwrap();
+    let requested = requested.unwrap();
+    assert_eq!(requested.size(), layout.stride() * 3);
+    assert_eq!(requested.align(), percpu_area_align());
+    assert_eq!(layout.cpu_num(), 3);
+    assert_eq!(percpu_area_num(), 3);
+    assert_eq!(percpu_area_capacity(), 3);
+    assert_eq!(try_init(3), Err(InitError::AlreadyInitialized));
+
+    for cpu in 0..3 {
+        assert_eq!(percpu_area_base(cpu), layout.area_base(cpu));
+        assert_eq!(unsafe { VALUE.read_remote(cpu) }, 0x1234_5678);
+        assert_eq!(unsafe { COUNT.read_remote(cpu) }, 0);
+    }
+
+    set_local_thread_pointer(2);
+    VALUE.write_current(1);
+    COUNT.add_current(2);
+    assert_eq!(unsafe { VALUE.read_remote(2) }, 1);
+    assert_eq!(unsafe { COUNT.read_remote(2) }, 2);
+    assert_eq!(unsafe { VALUE.read_remote(0) }, 0x1234_5678);
+}
@@ -0,0 +1,31 @@
+#![cfg(all(not(target_os = "macos"), not(feature = "sp-naive")))]
+
+use percpu::*;
+
+#[def_percpu]
+static COUNT: usize = 0;
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_many_cpus() {
+    // as many CPUs as the global masks can track.
+    let num = CpuMask::CAPACITY;
+    assert_eq!(try_init(num + 1), Err(InitError::TooManyCpus));
+    init(num);
+    assert_eq!(percpu_area_num(), num);
+    assert_eq!(cpu_possible_mask(), !CpuMask::new());
+    assert_eq!(cpu_online_mask(), CpuMask::first(num));
+    assert!(cpu_online(num - 1));
+
+    for cpu in 0..num {
+        unsafe { COUNT.write_remote(cpu, cpu) };
+    }
+    assert_eq!(unsafe { COUNT.iter_remote() }.len(), num);
+    assert_eq!(COUNT.max_by(|a, b| a.cmp(b)), Some((num - 1, num - 1)));
+    set_cpu_online(num - 1, false);
+    assert_eq!(COUNT.sum(), (num - 1) * (num - 2) / 2);
+    assert_eq!(COUNT.max_by(|a, b| a.cmp(b)), Some((num - 2, num - 2)));
+
+    set_local_thread_pointer(num - 1);
+    assert_eq!(COUNT.read_current(), num - 1);
+}
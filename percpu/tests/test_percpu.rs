@@ -2,8 +2,6 @@
 
 use percpu::*;
 
-// Initial value is unsupported for testing.
-
 #[def_percpu]
 static BOOL: bool = false;
 
@@ -22,6 +20,27 @@ static U64: u64 = 0;
 #[def_percpu]
 static USIZE: usize = 0;
 
+#[def_percpu]
+static I8: i8 = 0;
+
+#[def_percpu]
+static I16: i16 = 0;
+
+#[def_percpu]
+static I32: i32 = 0;
+
+#[def_percpu]
+static I64: i64 = 0;
+
+#[def_percpu]
+static ISIZE: isize = 0;
+
+#[def_percpu]
+static PTR: *mut Struct = core::ptr::null_mut();
+
+#[def_percpu]
+static NON_NULL: Option<core::ptr::NonNull<u64>> = None;
+
 struct Struct {
     foo: usize,
     bar: u8,
@@ -30,17 +49,179 @@ struct Struct {
 #[def_percpu]
 static STRUCT: Struct = Struct { foo: 0, bar: 0 };
 
+#[def_percpu]
+static PAIR: (u32, u32) = (0, 0);
+
+#[def_percpu]
+static IRQ_COUNTS: [u64; 16] = [0; 16];
+
+#[def_percpu]
+static FLAGS: [bool; 3] = [false; 3];
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct Record {
+    id: u64,
+    flags: u32,
+    len: u32,
+}
+
+#[def_percpu]
+static RECORD: Record = Record {
+    id: 0,
+    flags: 0,
+    len: 0,
+};
+
+struct Queue {
+    head: u16,
+    len: u32,
+}
+
+struct Stats {
+    rx: u64,
+    dropped: i32,
+    last: *const u8,
+    queue: Queue,
+}
+
+#[def_percpu]
+static STATS: Stats = Stats {
+    rx: 0,
+    dropped: 0,
+    last: core::ptr::null(),
+    queue: Queue { head: 0, len: 0 },
+};
+
+mod net {
+    pub struct NetStats {
+        pub rx: u64,
+        pub tx: u64,
+    }
+
+    #[percpu::def_percpu]
+    pub static NET_STATS: NetStats = NetStats { rx: 0, tx: 0 };
+
+    // A per-CPU static variable may share its name with a type or module, and may be configured out.
+    #[allow(non_snake_case)]
+    pub mod QUEUES {
+        pub const NUM: usize = 2;
+    }
+
+    #[percpu::def_percpu]
+    pub static QUEUES: [u64; QUEUES::NUM] = [0; QUEUES::NUM];
+
+    #[percpu::def_percpu]
+    #[cfg(any())]
+    pub static DISABLED: u64 = 0;
+}
+
+#[def_percpu(first)]
+static HOT: u32 = 0;
+
+#[def_percpu(cacheline_aligned)]
+static ALIGNED: u16 = 1;
+
+#[def_percpu(read_mostly)]
+static MOSTLY_READ: usize = 0;
+
+#[def_percpu]
+static INITIALIZED: u32 = 0x1234_5678;
+
+#[def_percpu(cacheline_aligned)]
+static INITIALIZED_STATS: Stats = Stats {
+    rx: 100,
+    dropped: -1,
+    last: core::ptr::null(),
+    queue: Queue { head: 3, len: 4 },
+};
+
+#[def_percpu(zeroed)]
+static ZEROED_STATS: Stats = Stats {
+    rx: 0,
+    dropped: 0,
+    last: core::ptr::null(),
+    queue: Queue { head: 0, len: 0 },
+};
+
+#[repr(C, align(4096))]
+struct PageAligned([u8; 4096]);
+
+#[def_percpu]
+static PAGE: PageAligned = PageAligned([0; 4096]);
+
+#[cfg(feature = "debug-checks")]
+fn assert_panics_with<T>(f: impl FnOnce() -> T + std::panic::UnwindSafe, msg: &str) {
+    let err = std::panic::catch_unwind(f).err().expect("no panic");
+    let err_msg = err
+        .downcast_ref::<String>()
+        .map(String::as_str)
+        .or_else(|| err.downcast_ref::<&str>().copied())
+        .unwrap_or_default();
+    assert_eq!(err_msg, msg);
+}
+
 #[cfg(target_os = "linux")]
 #[test]
 fn test_percpu() {
     println!("feature = \"sp-naive\": {}", cfg!(feature = "sp-naive"));
 
+    #[cfg(feature = "debug-checks")]
+    {
+        #[cfg(not(feature = "sp-naive"))]
+        assert_panics_with(|| U8.read_current(), "per-CPU access before percpu::init");
+        assert_panics_with(
+            || unsafe { U8.remote_ptr(0) },
+            "per-CPU access before percpu::init",
+        );
+    }
+
+    assert_eq!(try_init(0), Err(InitError::ZeroCpus));
+    assert_eq!(init_primary(0), Err(InitError::ZeroCpus));
+    assert_eq!(try_init(CpuMask::CAPACITY + 1), Err(InitError::TooManyCpus));
+    let layout = try_init(4).unwrap();
+    assert_eq!(layout.cpu_num(), 4);
+    assert_eq!(percpu_area_num(), 4);
+    assert_eq!(try_init(4), Err(InitError::AlreadyInitialized));
+    assert_eq!(try_init(8), Err(InitError::AlreadyInitialized));
+    assert_eq!(init_primary(4), Err(InitError::AlreadyInitialized));
+    init(4); // ignored
+    assert_eq!(percpu_area_num(), 4);
+    #[cfg(feature = "debug-checks")]
+    {
+        #[cfg(not(feature = "sp-naive"))]
+        assert_panics_with(|| U8.read_current(), "thread pointer not set on this CPU");
+        assert_panics_with(
+            || unsafe { U8.remote_ptr(4) },
+            "invalid CPU ID 4: only 4 per-CPU data areas are initialized",
+        );
+    }
+    #[cfg(not(feature = "sp-naive"))]
+    {
+        assert_eq!(percpu_area_capacity(), 4);
+        #[cfg(debug_assertions)]
+        assert!(std::panic::catch_unwind(|| unsafe { U8.remote_ptr(4) }).is_err());
+    }
+
     #[cfg(feature = "sp-naive")]
-    let base = 0;
+    let base = {
+        assert_eq!(layout.area_base(3), 0);
+        0
+    };
 
     #[cfg(not(feature = "sp-naive"))]
     let base = {
-        init(4);
+        assert_eq!(layout.base(), percpu_area_base(0));
+        assert_eq!(layout.area_base(3), percpu_area_base(3));
+        assert_eq!(layout.area_size(), percpu_area_size());
+        assert_eq!(layout.align(), 4096);
+        assert_eq!(percpu_area_align(), 4096);
+        assert_eq!(layout.stride() % 4096, 0);
+        for cpu in 0..4 {
+            assert_eq!(layout.area_base(cpu) % 4096, 0);
+            assert_eq!(unsafe { PAGE.remote_ptr(cpu) } as usize % 4096, 0);
+        }
+        assert!(layout.stride() >= layout.area_size());
         set_local_thread_pointer(0);
 
         let base = get_local_thread_pointer();
@@ -49,6 +230,52 @@ fn test_percpu() {
         base
     };
 
+    // test initial values
+    assert_eq!(INITIALIZED.read_current(), 0x1234_5678);
+    INITIALIZED.with_current(|v| *v += 1);
+    assert_eq!(INITIALIZED.read_current(), 0x1234_5679);
+    INITIALIZED_STATS.with_current(|s| {
+        assert_eq!((s.rx, s.dropped), (100, -1));
+        assert_eq!((s.queue.head, s.queue.len), (3, 4));
+    });
+    ZEROED_STATS.with_current(|s| {
+        assert_eq!((s.rx, s.dropped), (0, 0));
+        assert!(s.last.is_null());
+        assert_eq!((s.queue.head, s.queue.len), (0, 0));
+    });
+    #[cfg(not(feature = "sp-naive"))]
+    {
+        for cpu in 1..4 {
+            assert_eq!(unsafe { INITIALIZED.read_remote(cpu) }, 0x1234_5678);
+            assert_eq!(unsafe { INITIALIZED_STATS.remote_ref_raw(cpu).rx }, 100);
+            assert_eq!(unsafe { ZEROED_STATS.remote_ref_raw(cpu).rx }, 0);
+        }
+        unsafe { INITIALIZED.write_remote(2, 0) };
+        ZEROED_STATS.with_current(|s| s.rx = 1);
+        unsafe { reset_cpu_area(0) };
+        assert_eq!(INITIALIZED.read_current(), 0x1234_5678);
+        assert_eq!(unsafe { INITIALIZED.read_remote(2) }, 0);
+        assert_eq!(ZEROED_STATS.with_current(|s| s.rx), 0);
+        unsafe { reset_cpu_area(2) };
+        assert_eq!(unsafe { INITIALIZED.read_remote(2) }, 0x1234_5678);
+        assert!(std::panic::catch_unwind(|| unsafe { reset_cpu_area(4) }).is_err());
+
+        // a secondary CPU initializes its own area, on another thread with its own thread pointer.
+        unsafe { INITIALIZED.write_remote(1, 0) };
+        std::thread::spawn(|| {
+            unsafe { init_secondary(1) };
+            assert_eq!(get_local_thread_pointer(), percpu_area_base(1));
+            assert_eq!(INITIALIZED.read_current(), 0x1234_5678);
+            INITIALIZED.write_current(1);
+        })
+        .join()
+        .unwrap();
+        assert_eq!(unsafe { INITIALIZED.read_remote(1) }, 1);
+        assert!(std::panic::catch_unwind(|| unsafe { init_secondary(4) }).is_err());
+        // zero-initialized variables are placed after the others, in `.percpu.bss`.
+        assert!(ZEROED_STATS.offset() > INITIALIZED_STATS.offset());
+    }
+
     println!("bool offset: {:#x}", BOOL.offset());
     println!("u8 offset: {:#x}", U8.offset());
     println!("u16 offset: {:#x}", U16.offset());
@@ -129,6 +356,23 @@ fn test_percpu() {
         assert_eq!(s.bar, 200);
     }
 
+    // test remote read/write with volatile accessors
+    assert!(!unsafe { BOOL.read_remote(1) });
+    assert_eq!(unsafe { U64.read_remote(1) }, 0xfeed_feed_feed_feed);
+    #[cfg(not(feature = "sp-naive"))]
+    {
+        unsafe { U32.write_remote(2, 0xcafe_babe) };
+        unsafe { USIZE.write_remote(2, 0x1234_5678) };
+        assert_eq!(unsafe { U32.read_remote(2) }, 0xcafe_babe);
+        assert_eq!(unsafe { USIZE.read_remote(2) }, 0x1234_5678);
+        unsafe {
+            U8.write_remote_raw(3, 33);
+            assert_eq!(U8.read_remote_raw(3), 33);
+            assert_eq!(*U8.remote_ptr(3), 33);
+        }
+    }
+    assert!(std::panic::catch_unwind(|| unsafe { U32.read_remote(4) }).is_err());
+
     // test read on another CPU
     set_local_thread_pointer(1); // we are now on CPU 1
 
@@ -152,4 +396,450 @@ fn test_percpu() {
         assert_eq!(s.foo, 0x6666);
         assert_eq!(s.bar, 200);
     });
+
+    // test arithmetic and bitwise operations
+    U8.add_current(34);
+    assert_eq!(U8.read_current(), 0);
+    U16.sub_current(0x34);
+    assert_eq!(U16.read_current(), 0x1200);
+    U32.and_current(0xffff_0000);
+    assert_eq!(U32.read_current(), 0xf00d_0000);
+    U32.or_current(0x0000_beef);
+    assert_eq!(U32.read_current(), 0xf00d_beef);
+    U64.xor_current(0xffff_ffff_0000_0000);
+    assert_eq!(U64.read_current(), 0x0112_0112_feed_feed);
+    USIZE.inc_current();
+    USIZE.inc_current();
+    USIZE.dec_current();
+    assert_eq!(USIZE.read_current(), 0x0001_0000);
+    USIZE.write_current(0);
+    USIZE.dec_current();
+    assert_eq!(USIZE.read_current(), usize::MAX);
+
+    // test compare-exchange and exchange
+    assert_eq!(
+        U32.cmpxchg_current(0xf00d_beef, 0x1111_2222),
+        Ok(0xf00d_beef)
+    );
+    assert_eq!(
+        U32.cmpxchg_current(0xf00d_beef, 0x3333_4444),
+        Err(0x1111_2222)
+    );
+    assert_eq!(U32.read_current(), 0x1111_2222);
+    assert_eq!(U8.xchg_current(0xaa), 0);
+    assert_eq!(U8.read_current(), 0xaa);
+    assert_eq!(U16.xchg_current(0x5555), 0x1200);
+    assert_eq!(
+        U64.cmpxchg_current(0x0112_0112_feed_feed, 1),
+        Ok(0x0112_0112_feed_feed)
+    );
+    assert_eq!(USIZE.xchg_current(7), usize::MAX);
+    assert_eq!(BOOL.cmpxchg_current(false, true), Ok(false));
+    assert_eq!(BOOL.cmpxchg_current(false, true), Err(true));
+    assert!(BOOL.xchg_current(false));
+    assert!(!BOOL.read_current());
+
+    // test signed integers and pointers
+    I8.write_current(-100);
+    I16.write_current(-0x1234);
+    I32.write_current(-0x1234_5678);
+    I64.write_current(i64::MIN);
+    ISIZE.write_current(-1);
+    assert_eq!(I8.read_current(), -100);
+    assert_eq!(I16.read_current(), -0x1234);
+    assert_eq!(I32.read_current(), -0x1234_5678);
+    assert_eq!(I64.read_current(), i64::MIN);
+    assert_eq!(ISIZE.read_current(), -1);
+    I8.sub_current(100);
+    assert_eq!(I8.read_current(), 56);
+    ISIZE.add_current(-9);
+    assert_eq!(ISIZE.read_current(), -10);
+    assert_eq!(I32.cmpxchg_current(-0x1234_5678, 42), Ok(-0x1234_5678));
+    assert_eq!(I64.xchg_current(-1), i64::MIN);
+
+    let mut s = Struct { foo: 0, bar: 0 };
+    let mut val = 0xdead_u64;
+    let s_ptr: *mut Struct = &mut s;
+    let val_ptr = core::ptr::NonNull::from(&mut val);
+    assert!(PTR.read_current().is_null());
+    assert_eq!(NON_NULL.read_current(), None);
+    PTR.write_current(s_ptr);
+    NON_NULL.write_current(Some(val_ptr));
+    assert_eq!(PTR.read_current(), s_ptr);
+    assert_eq!(NON_NULL.read_current(), Some(val_ptr));
+    assert_eq!(
+        unsafe { *NON_NULL.read_current().unwrap().as_ptr() },
+        0xdead
+    );
+    assert_eq!(PTR.cmpxchg_current(s_ptr, core::ptr::null_mut()), Ok(s_ptr));
+    assert_eq!(NON_NULL.xchg_current(None), Some(val_ptr));
+    assert!(PTR.read_current().is_null());
+    assert_eq!(unsafe { NON_NULL.read_remote(1) }, None);
+
+    // test copy, replace and take for any type
+    PAIR.set_current((1, 2));
+    assert_eq!(PAIR.get_current(), (1, 2));
+    assert_eq!(PAIR.replace_current((3, 4)), (1, 2));
+    assert_eq!(PAIR.take_current(), (3, 4));
+    assert_eq!(PAIR.get_current(), (0, 0));
+
+    let record = Record {
+        id: 0x1234,
+        flags: 0x10,
+        len: 64,
+    };
+    RECORD.set_current(record);
+    assert_eq!(RECORD.get_current(), record);
+    assert_eq!(RECORD.take_current(), record);
+    assert_eq!(RECORD.get_current(), Record::default());
+
+    let old = STRUCT.replace_current(Struct { foo: 1, bar: 2 });
+    assert_eq!((old.foo, old.bar), (0x6666, 200));
+    STRUCT.with_current(|s| assert_eq!((s.foo, s.bar), (1, 2)));
+
+    // test references with a preemption token
+    {
+        let mut guard = PreemptGuard::new();
+        let token = guard.token();
+        let pair = unsafe { *PAIR.current_ref(&token) };
+        // mutable references of different variables with one token.
+        let (record, s) = unsafe { (RECORD.current_mut(&token), STRUCT.current_mut(&token)) };
+        record.id = 0x5678;
+        record.len = pair.0 + 1;
+        s.foo = 3;
+        assert_eq!(unsafe { RECORD.current_ref(&token) }.id, 0x5678);
+        assert_eq!(unsafe { RECORD.current_ref(&token) }.len, 1);
+    }
+    assert_eq!(RECORD.get_current().id, 0x5678);
+    STRUCT.with_current(|s| assert_eq!(s.foo, 3));
+
+    // test indexed access to per-CPU arrays
+    IRQ_COUNTS.write_current_at(3, 100);
+    IRQ_COUNTS.add_current_at(3, 5);
+    IRQ_COUNTS.add_current_at(15, 1);
+    assert_eq!(IRQ_COUNTS.read_current_at(3), 105);
+    assert_eq!(IRQ_COUNTS.read_current_at(15), 1);
+    assert_eq!(IRQ_COUNTS.read_current_at(0), 0);
+    IRQ_COUNTS.with_current(|counts| assert_eq!(counts.iter().sum::<u64>(), 106));
+    FLAGS.write_current_at(2, true);
+    assert!(!FLAGS.read_current_at(1));
+    assert!(FLAGS.read_current_at(2));
+    assert!(std::panic::catch_unwind(|| IRQ_COUNTS.add_current_at(16, 1)).is_err());
+    assert!(std::panic::catch_unwind(|| FLAGS.read_current_at(3)).is_err());
+
+    // test projected fields of per-CPU structs
+    let rx = percpu_field!(STATS.rx);
+    let dropped = percpu_field!(STATS.dropped);
+    let last = percpu_field!(STATS.last);
+    let queue_len = percpu_field!(STATS.queue.len);
+    let pair_1 = percpu_field!(PAIR.1);
+    // only the variable is imported, or given by its path.
+    let net_tx = {
+        use net::NET_STATS;
+        percpu_field!(NET_STATS.tx)
+    };
+    let net_rx = percpu_field!(net::NET_STATS.rx);
+    net::QUEUES.write_current_at(net::QUEUES::NUM - 1, 1);
+    assert_eq!(net::QUEUES.read_current_at(1), 1);
+    net_tx.write_current(2);
+    net_rx.add_current(1);
+    net::NET_STATS.with_current(|s| assert_eq!((s.rx, s.tx), (1, 2)));
+    assert_eq!(rx.offset(), STATS.offset());
+    unsafe {
+        assert_eq!(
+            queue_len.current_ptr(),
+            &(*STATS.current_ptr()).queue.len as *const u32
+        );
+        assert_eq!(rx.remote_ptr(0) as usize, STATS.remote_ptr(0) as usize);
+    }
+    rx.write_current(10);
+    rx.add_current(5);
+    dropped.add_current(-3);
+    queue_len.write_current(0x1234_5678);
+    percpu_field!(STATS.queue.head).write_current(0xffff);
+    last.write_current(s_ptr as *const u8);
+    pair_1.write_current(9);
+    assert_eq!(rx.read_current(), 15);
+    assert_eq!(dropped.read_current(), -3);
+    assert_eq!(queue_len.read_current(), 0x1234_5678);
+    assert_eq!(last.read_current(), s_ptr as *const u8);
+    assert_eq!(PAIR.get_current(), (0, 9));
+    STATS.with_current(|s| {
+        assert_eq!((s.rx, s.dropped), (15, -3));
+        assert_eq!((s.queue.head, s.queue.len), (0xffff, 0x1234_5678));
+    });
+
+    // test typed pointers to per-CPU data
+    struct Counter {
+        name: &'static str,
+        ptr: PerCpuPtr<u32>,
+    }
+    let counters = [
+        Counter {
+            name: "u32",
+            ptr: (&U32).into(),
+        },
+        Counter {
+            name: "initialized",
+            ptr: PerCpuPtr::from(&INITIALIZED),
+        },
+    ];
+    for (i, counter) in counters.iter().enumerate() {
+        counter.ptr.write_current(i as u32 + 10);
+        counter.ptr.with_current(|v| *v += 1);
+    }
+    assert_eq!(counters[0].name, "u32");
+    assert_eq!(counters[0].ptr.offset(), U32.offset());
+    assert_eq!(U32.read_current(), 11);
+    assert_eq!(INITIALIZED.read_current(), 12);
+    assert_eq!(unsafe { counters[1].ptr.current_ptr() }, unsafe {
+        INITIALIZED.current_ptr()
+    });
+    #[cfg(not(feature = "sp-naive"))]
+    assert_eq!(unsafe { *counters[1].ptr.remote_ptr(3) }, 0x1234_5678);
+    let record: PerCpuPtr<Record> = (&RECORD).into();
+    record.with_current(|r| r.len = 3);
+    assert_eq!(RECORD.with_current(|r| r.len), 3);
+
+    // test generic code on per-CPU variables
+    fn bump<V: PerCpuVar<Target = u32>>(var: &V) -> u32 {
+        var.with_current(|v| {
+            *v += 1;
+            *v
+        })
+    }
+    fn swap<V: PerCpuPrimitive>(a: &V, b: &V) {
+        let (va, vb) = (a.read_current(), b.read_current());
+        a.write_current(vb);
+        b.write_current(va);
+    }
+    fn offset_of<V: PerCpuVar>(var: &V) -> usize {
+        var.offset()
+    }
+    U32.write_current(1);
+    assert_eq!(bump(&U32), 2);
+    assert_eq!(bump(&counters[0].ptr), 3);
+    swap(&counters[0].ptr, &counters[1].ptr);
+    assert_eq!((U32.read_current(), INITIALIZED.read_current()), (12, 3));
+    swap(&U64, &U64);
+    assert_eq!(offset_of(&RECORD), RECORD.offset());
+    assert_eq!(offset_of(&record), RECORD.offset());
+    assert_eq!(unsafe { PerCpuVar::remote_ptr(&INITIALIZED, 0) }, unsafe {
+        INITIALIZED.remote_ptr(0)
+    });
+
+    // test iteration and aggregation across CPUs
+    let cpus = if cfg!(feature = "sp-naive") { 1 } else { 4 };
+    for cpu in 0..cpus {
+        unsafe { U64.write_remote(cpu, cpu as u64 * 10 + 1) };
+    }
+    assert_eq!(U64.sum(), if cfg!(feature = "sp-naive") { 1 } else { 64 });
+    #[cfg(not(feature = "sp-naive"))]
+    {
+        // the sum wraps around, e.g., for a counter incremented on CPU 0 and decremented on CPU 1.
+        for (cpu, v) in [1, usize::MAX, 0, 0].into_iter().enumerate() {
+            unsafe { USIZE.write_remote(cpu, v) };
+        }
+        assert_eq!(USIZE.sum(), 0);
+    }
+    assert_eq!(U64.fold(0, |acc, v| acc.max(v)), cpus as u64 * 10 - 9);
+    assert_eq!(U64.max_by(|a, b| b.cmp(a)), Some((0, 1)));
+    assert_eq!(
+        U64.max_by(|a, b| a.cmp(b)),
+        Some((cpus - 1, cpus as u64 * 10 - 9))
+    );
+    let iter = unsafe { U64.iter_remote() };
+    assert_eq!(iter.len(), cpus);
+    for (cpu, v) in iter {
+        assert_eq!(*v, cpu as u64 * 10 + 1);
+    }
+    let record_ids: Vec<_> = unsafe { RECORD.iter_remote() }.map(|(_, r)| r.id).collect();
+    assert_eq!(record_ids.len(), cpus);
+    // per-CPU structs are aggregated in the same way.
+    for cpu in 0..cpus {
+        unsafe { (*(RECORD.remote_ptr(cpu) as *mut Record)).flags = cpu as u32 + 1 };
+    }
+    assert_eq!(
+        RECORD.fold(0, |acc, r| acc + r.flags),
+        (cpus * (cpus + 1) / 2) as u32
+    );
+    assert_eq!(
+        RECORD
+            .max_by(|a, b| a.flags.cmp(&b.flags))
+            .map(|(cpu, r)| (cpu, r.flags)),
+        Some((cpus - 1, cpus as u32))
+    );
+    #[cfg(feature = "alloc")]
+    assert_eq!(RECORD.snapshot().len(), cpus);
+    #[cfg(feature = "alloc")]
+    assert_eq!(
+        U64.snapshot(),
+        (0..cpus as u64).map(|cpu| cpu * 10 + 1).collect::<Vec<_>>()
+    );
+
+    // test CPU masks
+    assert_eq!(cpu_possible_mask(), CpuMask::first(4));
+    assert_eq!(cpu_present_mask(), CpuMask::first(4));
+    assert_eq!(cpu_online_mask(), CpuMask::first(4));
+    set_cpu_online(2, false);
+    assert!(!cpu_online(2) && cpu_online(3));
+    #[cfg(not(feature = "sp-naive"))]
+    {
+        // offline CPUs are skipped.
+        assert_eq!(U64.sum(), 64 - 21);
+        let cpus: Vec<_> = unsafe { U64.iter_remote() }.map(|(cpu, _)| cpu).collect();
+        assert_eq!(cpus, [0, 1, 3]);
+    }
+    set_cpu_present(3, false);
+    assert_eq!(cpu_online_mask().iter().collect::<Vec<_>>(), [0, 1]);
+    assert!(std::panic::catch_unwind(|| set_cpu_online(3, true)).is_err());
+    assert!(std::panic::catch_unwind(|| set_cpu_present(4, true)).is_err());
+    set_cpu_present(3, true);
+    set_cpu_online(3, true);
+    set_cpu_online(2, true);
+    assert_eq!(cpu_online_mask(), CpuMask::first(4));
+
+    let (mid, last) = (CpuMask::CAPACITY / 2, CpuMask::CAPACITY - 1);
+    let mut mask: CpuMask = [1, mid, last].into_iter().collect();
+    assert_eq!(mask.weight(), 3);
+    assert_eq!(mask.first_set(), Some(1));
+    mask.clear(1);
+    assert!(!mask.contains(1) && mask.contains(mid) && !mask.contains(CpuMask::CAPACITY));
+    assert_eq!(format!("{:?}", mask), format!("{{{}, {}}}", mid, last));
+    assert_eq!((!mask).weight(), CpuMask::CAPACITY - 2);
+    assert_eq!(mask & CpuMask::first(mid + 1), [mid].into_iter().collect());
+    assert_eq!((mask | CpuMask::first(2)).iter().len(), 4);
+    assert!(CpuMask::new().is_empty() && CpuMask::new().first_set().is_none());
+    assert_eq!(CpuMask::first(CpuMask::CAPACITY), !CpuMask::new());
+
+    // test placement in subsections and alignment
+    assert_eq!(ALIGNED.offset() % 64, 0);
+    assert_eq!(PAGE.offset() % 4096, 0);
+    assert_eq!(unsafe { PAGE.current_ptr() } as usize % 4096, 0);
+    #[cfg(not(feature = "sp-naive"))]
+    {
+        assert!(HOT.offset() < ALIGNED.offset());
+        assert!(ALIGNED.offset() + 64 <= MOSTLY_READ.offset());
+        assert!(MOSTLY_READ.offset() < U8.offset());
+    }
+    HOT.write_current(1);
+    ALIGNED.write_current(2);
+    MOSTLY_READ.write_current(3);
+    ALIGNED.add_current(3);
+    assert_eq!(HOT.read_current(), 1);
+    assert_eq!(ALIGNED.read_current(), 5);
+    assert_eq!(ALIGNED.get_current(), 5);
+    assert_eq!(MOSTLY_READ.read_current(), 3);
+    assert_eq!(unsafe { *ALIGNED.remote_ptr(1) }, 5);
+
+    // test per-CPU data allocated at runtime
+    #[cfg(feature = "dynamic")]
+    {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+        #[derive(Default)]
+        struct Droppable(u64);
+
+        #[repr(align(64))]
+        #[derive(Default)]
+        struct CachelinePadded;
+
+        #[repr(align(128))]
+        #[derive(Default)]
+        struct OverAligned;
+
+        type Block = [[u64; 32]; 4];
+
+        impl Drop for Droppable {
+            fn drop(&mut self) {
+                DROPPED.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        let counter = alloc_percpu::<u64>().unwrap();
+        let record = alloc_percpu::<Record>().unwrap();
+        let aligned = alloc_percpu::<CachelinePadded>().unwrap();
+        assert_ne!(counter.offset(), record.offset());
+        assert_eq!(aligned.offset() % 64, 0);
+        assert!(alloc_percpu::<OverAligned>().is_none());
+
+        counter.add_current(3);
+        counter.add_current(4);
+        assert_eq!(counter.read_current(), 7);
+        record.with_current(|r| r.id = 5);
+        assert_eq!(unsafe { record.current_ref_raw().id }, 5);
+        let current = (0..4)
+            .find(|&cpu| percpu_area_base(cpu) == get_local_thread_pointer())
+            .unwrap();
+        assert_eq!(unsafe { *counter.remote_ptr(current) }, 7);
+        #[cfg(not(feature = "sp-naive"))]
+        for cpu in (0..4).filter(|&cpu| cpu != current) {
+            assert_eq!(unsafe { *counter.remote_ptr(cpu) }, 0);
+            assert_eq!(unsafe { *record.remote_ref_raw(cpu) }, Record::default());
+        }
+        unsafe { record.remote_ref_mut_raw(current).len = 6 };
+        assert_eq!(record.with_current(|r| (r.id, r.len)), (5, 6));
+        drop((record, aligned));
+
+        // the space is freed on drop.
+        let offset = counter.offset();
+        drop(counter);
+        assert_eq!(alloc_percpu::<u64>().unwrap().offset(), offset);
+        let boxes: Vec<_> = std::iter::from_fn(alloc_percpu::<Block>).collect();
+        assert_eq!(boxes.len(), DYNAMIC_CHUNK_SIZE / size_of::<Block>());
+        drop(boxes);
+        assert!(alloc_percpu::<Block>().is_some());
+
+        // the per-CPU data on all CPUs is dropped.
+        let droppable = alloc_percpu::<Droppable>().unwrap();
+        droppable.with_current(|d| d.0 = 1);
+        drop(droppable);
+        let expected = if cfg!(feature = "sp-naive") { 1 } else { 4 };
+        assert_eq!(DROPPED.load(Ordering::Relaxed), expected);
+
+        // the per-CPU data is dropped, and initialized to the default value again when the area is reset.
+        #[cfg(not(feature = "sp-naive"))]
+        {
+            let droppable = alloc_percpu::<Droppable>().unwrap();
+            droppable.with_current(|d| d.0 = 1);
+            unsafe { reset_cpu_area(current) };
+            assert_eq!(DROPPED.load(Ordering::Relaxed), 5);
+            assert_eq!(droppable.with_current(|d| d.0), 0);
+            drop(droppable);
+            assert_eq!(DROPPED.load(Ordering::Relaxed), 9);
+
+            let boxed = alloc_percpu::<Box<u64>>().unwrap();
+            boxed.with_current(|b| **b = 1);
+            unsafe { reset_cpu_area(current) };
+            assert_eq!(boxed.with_current(|b| **b), 0);
+            drop(boxed);
+        }
+    }
+}
+
+/// The space reserved by the linker script holds the template and `CPU_NUM` per-CPU data areas, which is what
+/// `percpu_area_capacity` counts on bare metal. On hosted Linux, the areas are allocated in `init` instead.
+#[cfg(all(target_os = "linux", not(feature = "sp-naive")))]
+#[test]
+fn test_reserved_space() {
+    /// `CPU_NUM` in `test_percpu.x`.
+    const LINKER_CPU_NUM: usize = 4;
+    extern "C" {
+        static _percpu_start: u8;
+        static _percpu_end: u8;
+    }
+    let start = core::ptr::addr_of!(_percpu_start) as usize;
+    let end = core::ptr::addr_of!(_percpu_end) as usize;
+    let stride = percpu_area_size().next_multiple_of(percpu_area_align());
+    println!(
+        "reserved per-CPU space = {:#x}, stride = {:#x}",
+        end - start,
+        stride
+    );
+    assert_eq!(start % percpu_area_align(), 0);
+    assert_eq!((end - start) % stride, 0);
+    // the first area is the template.
+    assert!((end - start) / stride > LINKER_CPU_NUM);
 }
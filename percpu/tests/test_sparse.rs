@@ -0,0 +1,66 @@
+#![cfg(all(not(target_os = "macos"), not(feature = "sp-naive")))]
+
+use percpu::*;
+
+#[def_percpu]
+static VALUE: u64 = 0x1234_5678;
+
+#[def_percpu]
+static COUNT: usize = 0;
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_sparse() {
+    let layout =
+        std::alloc::Layout::from_size_align(percpu_area_size(), percpu_area_align()).unwrap();
+    let mut bases: Vec<usize> = (0..3)
+        .map(|_| unsafe { std::alloc::alloc(layout) } as usize)
+        .collect();
+    // not contiguous, nor in the ascending order.
+    bases.reverse();
+    let bases: &'static [usize] = Vec::leak(bases);
+
+    let misaligned: &'static [usize] = Vec::leak(vec![bases[0] + 8]);
+    assert_eq!(
+        unsafe { init_with_bases(misaligned) },
+        Err(InitError::Misaligned)
+    );
+    assert_eq!(unsafe { init_with_bases(&[]) }, Err(InitError::ZeroCpus));
+
+    let layout = unsafe { init_with_bases(bases) }.unwrap();
+    assert_eq!(layout.cpu_num(), 3);
+    assert_eq!(layout.stride(), 0);
+    assert_eq!(layout.bases(), Some(bases));
+    assert_eq!(percpu_area_num(), 3);
+    assert_eq!(percpu_area_capacity(), 3);
+    assert_eq!(try_init(3), Err(InitError::AlreadyInitialized));
+
+    for (cpu, &base) in bases.iter().enumerate() {
+        assert_eq!(layout.area_base(cpu), base);
+        assert_eq!(percpu_area_base(cpu), base);
+        assert_eq!(
+            unsafe { VALUE.remote_ptr(cpu) } as usize,
+            base + VALUE.offset()
+        );
+        assert_eq!(unsafe { VALUE.read_remote(cpu) }, 0x1234_5678);
+    }
+
+    // the per-CPU data on the current CPU is still accessed with the thread pointer.
+    set_local_thread_pointer(1);
+    assert_eq!(get_local_thread_pointer(), bases[1]);
+    VALUE.write_current(1);
+    COUNT.add_current(2);
+    assert_eq!(unsafe { VALUE.read_remote(1) }, 1);
+    assert_eq!(unsafe { COUNT.read_remote(1) }, 2);
+    assert_eq!(unsafe { VALUE.read_remote(0) }, 0x1234_5678);
+    assert_eq!(unsafe { COUNT.read_remote(2) }, 0);
+
+    std::thread::spawn(|| {
+        unsafe { init_secondary(2) };
+        assert_eq!(get_local_thread_pointer(), percpu_area_base(2));
+        COUNT.write_current(3);
+    })
+    .join()
+    .unwrap();
+    assert_eq!(unsafe { COUNT.read_remote(2) }, 3);
+}
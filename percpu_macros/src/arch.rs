@@ -1,6 +1,8 @@
 use quote::{format_ident, quote};
 use syn::{Ident, Type};
 
+use crate::fallback;
+
 fn macos_unimplemented(item: proc_macro2::TokenStream) -> proc_macro2::TokenStream {
     quote! {
         {
@@ -24,9 +26,12 @@ pub fn gen_offset(symbol: &Ident) -> proc_macro2::TokenStream {
                 out(reg) value,
                 VAR = sym #symbol,
             );
+            // The linker checks that the offset fits in 32 bits (`abs_g1`), or 2 GiB on RISC-V (`%hi`). On
+            // LoongArch, all 64 bits are loaded.
             #[cfg(target_arch = "aarch64")]
             ::core::arch::asm!(
-                "movz {0}, #:abs_g0_nc:{VAR}",
+                "movz {0}, #:abs_g1:{VAR}",
+                "movk {0}, #:abs_g0_nc:{VAR}",
                 out(reg) value,
                 VAR = sym #symbol,
             );
@@ -41,6 +46,8 @@ pub fn gen_offset(symbol: &Ident) -> proc_macro2::TokenStream {
             ::core::arch::asm!(
                 "lu12i.w {0}, %abs_hi20({VAR})",
                 "ori {0}, {0}, %abs_lo12({VAR})",
+                "lu32i.d {0}, %abs64_lo20({VAR})",
+                "lu52i.d {0}, {0}, %abs64_hi12({VAR})",
                 out(reg) value,
                 VAR = sym #symbol,
             );
@@ -49,6 +56,32 @@ pub fn gen_offset(symbol: &Ident) -> proc_macro2::TokenStream {
     }
 }
 
+/// Generate the items that record the initial value of the per-CPU variable, for the hosted Linux backend.
+///
+/// On hosted Linux, the `.percpu` section is not loaded from the ELF file, so a template of the initial value is
+/// kept in an ordinary static variable, and an entry describing it is placed in the `percpu_init` section, from which
+/// `percpu::init` copies the initial values to the per-CPU data areas.
+pub fn gen_hosted_init(
+    symbol: &Ident,
+    storage_ty: &proc_macro2::TokenStream,
+    storage_init: &proc_macro2::TokenStream,
+) -> proc_macro2::TokenStream {
+    quote! {
+        #[cfg(target_os = "linux")]
+        const _: () = {
+            static mut TEMPLATE: #storage_ty = #storage_init;
+
+            #[used]
+            #[link_section = "percpu_init"]
+            static ENTRY: percpu::__priv::InitEntry = percpu::__priv::InitEntry {
+                var: unsafe { ::core::ptr::addr_of!(#symbol).cast::<u8>() },
+                template: unsafe { ::core::ptr::addr_of!(TEMPLATE).cast::<u8>() },
+                size: ::core::mem::size_of::<#storage_ty>(),
+            };
+        };
+    }
+}
+
 /// Generate a code block that calculates the pointer to the per-CPU variable on the current CPU, based on the inner
 /// symbol name and the type of the variable.
 pub fn gen_current_ptr(symbol: &Ident, ty: &Type) -> proc_macro2::TokenStream {
@@ -90,7 +123,8 @@ pub fn gen_current_ptr(symbol: &Ident, ty: &Type) -> proc_macro2::TokenStream {
 /// Generate a code block that reads the value of the per-CPU variable on the current CPU, based on the inner symbol
 /// name and the type of the variable.
 ///
-/// The type of the variable must be one of the following: `bool`, `u8`, `u16`, `u32`, `u64`, or `usize`.
+/// The type of the variable must be `bool` or one of the primitive integer types (`u8`..`u64`, `usize`, `i8`..`i64`,
+/// or `isize`).
 pub fn gen_read_current_raw(symbol: &Ident, ty: &Type) -> proc_macro2::TokenStream {
     let ty_str = quote!(#ty).to_string();
     let rv64_op = match ty_str.as_str() {
@@ -100,6 +134,11 @@ pub fn gen_read_current_raw(symbol: &Ident, ty: &Type) -> proc_macro2::TokenStre
         "u32" => "lwu",
         "u64" => "ld",
         "usize" => "ld",
+        "i8" => "lb",
+        "i16" => "lh",
+        "i32" => "lw",
+        "i64" => "ld",
+        "isize" => "ld",
         _ => unreachable!(),
     };
     let rv64_asm = quote! {
@@ -120,29 +159,36 @@ pub fn gen_read_current_raw(symbol: &Ident, ty: &Type) -> proc_macro2::TokenStre
         "u32" => "ldx.wu",
         "u64" => "ldx.d",
         "usize" => "ldx.d",
+        "i8" => "ldx.b",
+        "i16" => "ldx.h",
+        "i32" => "ldx.w",
+        "i64" => "ldx.d",
+        "isize" => "ldx.d",
         _ => unreachable!(),
     };
     let la64_asm = quote! {
         ::core::arch::asm!(
             "lu12i.w {0}, %abs_hi20({VAR})",
             "ori {0}, {0}, %abs_lo12({VAR})",
+            "lu32i.d {0}, %abs64_lo20({VAR})",
+            "lu52i.d {0}, {0}, %abs64_hi12({VAR})",
             concat!(#la64_op, " {0}, {0}, $r21"),
             out(reg) value,
             VAR = sym #symbol,
         )
     };
 
-    let (x64_asm, x64_reg) = if ["bool", "u8"].contains(&ty_str.as_str()) {
+    let (x64_asm, x64_reg) = if ["bool", "u8", "i8"].contains(&ty_str.as_str()) {
         (
             "mov {0}, byte ptr gs:[offset {VAR}]".into(),
             format_ident!("reg_byte"),
         )
     } else {
         let (x64_mod, x64_ptr) = match ty_str.as_str() {
-            "u16" => ("x", "word"),
-            "u32" => ("e", "dword"),
-            "u64" => ("r", "qword"),
-            "usize" => ("r", "qword"),
+            "u16" | "i16" => ("x", "word"),
+            "u32" | "i32" => ("e", "dword"),
+            "u64" | "i64" => ("r", "qword"),
+            "usize" | "isize" => ("r", "qword"),
             _ => unreachable!(),
         };
         (
@@ -181,14 +227,15 @@ pub fn gen_read_current_raw(symbol: &Ident, ty: &Type) -> proc_macro2::TokenStre
         #[cfg(target_arch = "x86_64")]
         { #x64_code }
         #[cfg(not(any(target_arch = "riscv64", target_arch = "loongarch64", target_arch = "x86_64")))]
-        { *self.current_ptr() }
+        { *self.current_ptr().cast::<#ty>() }
     })
 }
 
 /// Generate a code block that writes the value of the per-CPU variable on the current CPU, based on the inner symbol
 /// name, the identifier of the value to write, and the type of the variable.
 ///
-/// The type of the variable must be one of the following: `bool`, `u8`, `u16`, `u32`, `u64`, or `usize`.
+/// The type of the variable must be `bool` or one of the primitive integer types (`u8`..`u64`, `usize`, `i8`..`i64`,
+/// or `isize`).
 pub fn gen_write_current_raw(symbol: &Ident, val: &Ident, ty: &Type) -> proc_macro2::TokenStream {
     let ty_str = quote!(#ty).to_string();
     let ty_fixup = if ty_str.as_str() == "bool" {
@@ -204,6 +251,11 @@ pub fn gen_write_current_raw(symbol: &Ident, val: &Ident, ty: &Type) -> proc_mac
         "u32" => "sw",
         "u64" => "sd",
         "usize" => "sd",
+        "i8" => "sb",
+        "i16" => "sh",
+        "i32" => "sw",
+        "i64" => "sd",
+        "isize" => "sd",
         _ => unreachable!(),
     };
     let rv64_code = quote! {
@@ -225,12 +277,19 @@ pub fn gen_write_current_raw(symbol: &Ident, val: &Ident, ty: &Type) -> proc_mac
         "u32" => "stx.w",
         "u64" => "stx.d",
         "usize" => "stx.d",
+        "i8" => "stx.b",
+        "i16" => "stx.h",
+        "i32" => "stx.w",
+        "i64" => "stx.d",
+        "isize" => "stx.d",
         _ => unreachable!(),
     };
     let la64_code = quote! {
         ::core::arch::asm!(
             "lu12i.w {0}, %abs_hi20({VAR})",
             "ori {0}, {0}, %abs_lo12({VAR})",
+            "lu32i.d {0}, %abs64_lo20({VAR})",
+            "lu52i.d {0}, {0}, %abs64_hi12({VAR})",
             concat!(#la64_op, " {1}, {0}, $r21"),
             out(reg) _,
             in(reg) #val as #ty_fixup,
@@ -238,17 +297,17 @@ pub fn gen_write_current_raw(symbol: &Ident, val: &Ident, ty: &Type) -> proc_mac
         );
     };
 
-    let (x64_asm, x64_reg) = if ["bool", "u8"].contains(&ty_str.as_str()) {
+    let (x64_asm, x64_reg) = if ["bool", "u8", "i8"].contains(&ty_str.as_str()) {
         (
             "mov byte ptr gs:[offset {VAR}], {0}".into(),
             format_ident!("reg_byte"),
         )
     } else {
         let (x64_mod, x64_ptr) = match ty_str.as_str() {
-            "u16" => ("x", "word"),
-            "u32" => ("e", "dword"),
-            "u64" => ("r", "qword"),
-            "usize" => ("r", "qword"),
+            "u16" | "i16" => ("x", "word"),
+            "u32" | "i32" => ("e", "dword"),
+            "u64" | "i64" => ("r", "qword"),
+            "usize" | "isize" => ("r", "qword"),
             _ => unreachable!(),
         };
         (
@@ -271,3 +330,287 @@ pub fn gen_write_current_raw(symbol: &Ident, val: &Ident, ty: &Type) -> proc_mac
         { *(self.current_ptr() as *mut #ty) = #val }
     })
 }
+
+/// Generate a code block that applies a read-modify-write operation to the per-CPU variable on the current CPU, based
+/// on the inner symbol name, the operation, the identifier of the operand, and the type of the variable.
+///
+/// The operation must be one of the following: `add`, `sub`, `and`, `or`, or `xor`. Arithmetic operations wrap around
+/// on overflow. The type of the variable must be one of the primitive integer types (`u8`..`u64`, `usize`, `i8`..`i64`,
+/// or `isize`).
+pub fn gen_op_current_raw(
+    symbol: &Ident,
+    op: &str,
+    val: &Ident,
+    ty: &Type,
+) -> proc_macro2::TokenStream {
+    let ty_str = quote!(#ty).to_string();
+
+    let (x64_asm, x64_reg) = if ["u8", "i8"].contains(&ty_str.as_str()) {
+        (
+            format!("{op} byte ptr gs:[offset {{VAR}}], {{0}}"),
+            format_ident!("reg_byte"),
+        )
+    } else {
+        let (x64_mod, x64_ptr) = match ty_str.as_str() {
+            "u16" | "i16" => ("x", "word"),
+            "u32" | "i32" => ("e", "dword"),
+            "u64" | "i64" => ("r", "qword"),
+            "usize" | "isize" => ("r", "qword"),
+            _ => unreachable!(),
+        };
+        (
+            format!("{op} {x64_ptr} ptr gs:[offset {{VAR}}], {{0:{x64_mod}}}"),
+            format_ident!("reg"),
+        )
+    };
+    let x64_code = quote! {
+        ::core::arch::asm!(#x64_asm, in(#x64_reg) #val, VAR = sym #symbol)
+    };
+
+    let fallback_code = fallback::gen_op(op, quote! { self.current_ptr() as *mut #ty }, val);
+    macos_unimplemented(quote! {
+        #[cfg(target_arch = "x86_64")]
+        { #x64_code }
+        #[cfg(not(target_arch = "x86_64"))]
+        { #fallback_code }
+    })
+}
+
+/// Generate a preemption guard for the safe read-modify-write operations.
+///
+/// On x86_64, the operations are single instructions which cannot be preempted, so the guard is only needed on other
+/// architectures.
+pub fn gen_op_guard(no_preempt_guard: &proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+    if no_preempt_guard.is_empty() {
+        return quote! {};
+    }
+    quote! {
+        #[cfg(not(target_arch = "x86_64"))]
+        #no_preempt_guard
+    }
+}
+
+/// Generate a code block that compares the per-CPU variable on the current CPU with `old`, and replaces it with `new`
+/// if they are equal, based on the inner symbol name, the identifiers of the old and new values, and the type of the
+/// variable. The code block evaluates to `Ok(previous)` on success, or `Err(current)` on failure.
+///
+/// The type of the variable must be `bool` or one of the primitive integer types (`u8`..`u64`, `usize`, `i8`..`i64`,
+/// or `isize`).
+pub fn gen_cmpxchg_current_raw(
+    symbol: &Ident,
+    old: &Ident,
+    new: &Ident,
+    ty: &Type,
+) -> proc_macro2::TokenStream {
+    let ty_str = quote!(#ty).to_string();
+    let ty_fixup = if ty_str.as_str() == "bool" {
+        format_ident!("u8")
+    } else {
+        format_ident!("{}", ty_str)
+    };
+    let (x64_acc, x64_ptr, x64_mod, x64_reg) = x64_operand(&ty_str);
+
+    // A non-locked `cmpxchg` is atomic with respect to interrupts on the local CPU.
+    let x64_asm = format!("cmpxchg {x64_ptr} ptr gs:[offset {{VAR}}], {{0{x64_mod}}}");
+    let x64_code = quote! {
+        let prev: #ty_fixup;
+        ::core::arch::asm!(
+            #x64_asm,
+            in(#x64_reg) #new as #ty_fixup,
+            inout(#x64_acc) #old as #ty_fixup => prev,
+            VAR = sym #symbol,
+        );
+        prev
+    };
+    let x64_code = if ty_str.as_str() == "bool" {
+        quote! {
+            let prev = { #x64_code } != 0;
+            if prev == #old { Ok(prev) } else { Err(prev) }
+        }
+    } else {
+        quote! {
+            let prev = { #x64_code };
+            if prev == #old { Ok(prev) } else { Err(prev) }
+        }
+    };
+
+    let fallback_code = fallback::gen_cmpxchg(quote! { self.current_ptr() }, old, new, ty);
+    macos_unimplemented(quote! {
+        #[cfg(target_arch = "x86_64")]
+        { #x64_code }
+        #[cfg(not(target_arch = "x86_64"))]
+        { #fallback_code }
+    })
+}
+
+/// Generate a code block that replaces the per-CPU variable on the current CPU with `new`, and evaluates to the
+/// previous value, based on the inner symbol name, the identifier of the new value, and the type of the variable.
+///
+/// The type of the variable must be `bool` or one of the primitive integer types (`u8`..`u64`, `usize`, `i8`..`i64`,
+/// or `isize`).
+pub fn gen_xchg_current_raw(symbol: &Ident, new: &Ident, ty: &Type) -> proc_macro2::TokenStream {
+    let ty_str = quote!(#ty).to_string();
+    let ty_fixup = if ty_str.as_str() == "bool" {
+        format_ident!("u8")
+    } else {
+        format_ident!("{}", ty_str)
+    };
+    let (x64_acc, x64_ptr, x64_mod, x64_reg) = x64_operand(&ty_str);
+
+    // `xchg` with a memory operand implies `lock`, so we use a non-locked `cmpxchg` loop instead, which is enough to
+    // be atomic with respect to interrupts on the local CPU.
+    let x64_load = format!("mov {x64_acc}, {x64_ptr} ptr gs:[offset {{VAR}}]");
+    let x64_cmpxchg = format!("cmpxchg {x64_ptr} ptr gs:[offset {{VAR}}], {{0{x64_mod}}}");
+    let x64_code = quote! {
+        let prev: #ty_fixup;
+        ::core::arch::asm!(
+            #x64_load,
+            "2:",
+            #x64_cmpxchg,
+            "jne 2b",
+            in(#x64_reg) #new as #ty_fixup,
+            out(#x64_acc) prev,
+            VAR = sym #symbol,
+        );
+    };
+    let x64_code = if ty_str.as_str() == "bool" {
+        quote! {
+            #x64_code
+            prev != 0
+        }
+    } else {
+        quote! {
+            #x64_code
+            prev
+        }
+    };
+
+    let fallback_code = fallback::gen_xchg(quote! { self.current_ptr() }, new, ty);
+    macos_unimplemented(quote! {
+        #[cfg(target_arch = "x86_64")]
+        { #x64_code }
+        #[cfg(not(target_arch = "x86_64"))]
+        { #fallback_code }
+    })
+}
+
+/// Returns the accumulator register, the pointer size directive, the register template modifier, and the register
+/// class of the x86_64 operand for the given type.
+fn x64_operand(ty_str: &str) -> (&'static str, &'static str, &'static str, proc_macro2::Ident) {
+    match ty_str {
+        "bool" | "u8" | "i8" => ("al", "byte", "", format_ident!("reg_byte")),
+        "u16" | "i16" => ("ax", "word", ":x", format_ident!("reg")),
+        "u32" | "i32" => ("eax", "dword", ":e", format_ident!("reg")),
+        "u64" | "usize" | "i64" | "isize" => ("rax", "qword", ":r", format_ident!("reg")),
+        _ => unreachable!(),
+    }
+}
+
+/// Generate a code block that reads the element at index `idx` of the per-CPU array on the current CPU, based on the
+/// inner symbol name, the identifier of the index, and the element type of the array.
+///
+/// The element type must be `bool` or one of the primitive integer types. The index must be in bounds.
+pub fn gen_read_current_at_raw(symbol: &Ident, idx: &Ident, ty: &Type) -> proc_macro2::TokenStream {
+    let ty_str = quote!(#ty).to_string();
+    let ty_fixup = if ty_str.as_str() == "bool" {
+        format_ident!("u8")
+    } else {
+        format_ident!("{}", ty_str)
+    };
+    let (_, x64_ptr, x64_mod, x64_reg) = x64_operand(&ty_str);
+    let x64_scale = x64_scale(x64_ptr);
+
+    let x64_asm =
+        format!("mov {{0{x64_mod}}}, {x64_ptr} ptr gs:[offset {{VAR}} + {{1}}*{x64_scale}]");
+    let x64_value = if ty_str.as_str() == "bool" {
+        quote! { value != 0 }
+    } else {
+        quote! { value }
+    };
+    macos_unimplemented(quote! {
+        #[cfg(target_arch = "x86_64")]
+        {
+            let value: #ty_fixup;
+            ::core::arch::asm!(#x64_asm, out(#x64_reg) value, in(reg) #idx, VAR = sym #symbol);
+            #x64_value
+        }
+        #[cfg(not(target_arch = "x86_64"))]
+        { *self.current_ptr().cast::<#ty>().add(#idx) }
+    })
+}
+
+/// Generate a code block that writes the element at index `idx` of the per-CPU array on the current CPU, based on the
+/// inner symbol name, the identifiers of the index and the value to write, and the element type of the array.
+///
+/// The element type must be `bool` or one of the primitive integer types. The index must be in bounds.
+pub fn gen_write_current_at_raw(
+    symbol: &Ident,
+    idx: &Ident,
+    val: &Ident,
+    ty: &Type,
+) -> proc_macro2::TokenStream {
+    let ty_str = quote!(#ty).to_string();
+    let ty_fixup = if ty_str.as_str() == "bool" {
+        format_ident!("u8")
+    } else {
+        format_ident!("{}", ty_str)
+    };
+    let (_, x64_ptr, x64_mod, x64_reg) = x64_operand(&ty_str);
+    let x64_scale = x64_scale(x64_ptr);
+
+    let x64_asm =
+        format!("mov {x64_ptr} ptr gs:[offset {{VAR}} + {{1}}*{x64_scale}], {{0{x64_mod}}}");
+    macos_unimplemented(quote! {
+        #[cfg(target_arch = "x86_64")]
+        {
+            ::core::arch::asm!(#x64_asm, in(#x64_reg) #val as #ty_fixup, in(reg) #idx, VAR = sym #symbol)
+        }
+        #[cfg(not(target_arch = "x86_64"))]
+        { *(self.current_ptr() as *mut #ty).add(#idx) = #val }
+    })
+}
+
+/// Generate a code block that applies a read-modify-write operation to the element at index `idx` of the per-CPU
+/// array on the current CPU, based on the inner symbol name, the operation, the identifiers of the index and the
+/// operand, and the element type of the array.
+///
+/// See [`gen_op_current_raw`] for the supported operations. The element type must be one of the primitive integer
+/// types. The index must be in bounds.
+pub fn gen_op_current_at_raw(
+    symbol: &Ident,
+    op: &str,
+    idx: &Ident,
+    val: &Ident,
+    ty: &Type,
+) -> proc_macro2::TokenStream {
+    let ty_str = quote!(#ty).to_string();
+    let (_, x64_ptr, x64_mod, x64_reg) = x64_operand(&ty_str);
+    let x64_scale = x64_scale(x64_ptr);
+
+    let x64_asm =
+        format!("{op} {x64_ptr} ptr gs:[offset {{VAR}} + {{1}}*{x64_scale}], {{0{x64_mod}}}");
+    let fallback_code = fallback::gen_op(
+        op,
+        quote! { (self.current_ptr() as *mut #ty).add(#idx) },
+        val,
+    );
+    macos_unimplemented(quote! {
+        #[cfg(target_arch = "x86_64")]
+        {
+            ::core::arch::asm!(#x64_asm, in(#x64_reg) #val, in(reg) #idx, VAR = sym #symbol)
+        }
+        #[cfg(not(target_arch = "x86_64"))]
+        { #fallback_code }
+    })
+}
+
+/// Returns the scale factor of the x86_64 index register for the given pointer size directive.
+fn x64_scale(x64_ptr: &str) -> usize {
+    match x64_ptr {
+        "byte" => 1,
+        "word" => 2,
+        "dword" => 4,
+        "qword" => 8,
+        _ => unreachable!(),
+    }
+}
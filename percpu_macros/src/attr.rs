@@ -0,0 +1,149 @@
+//! Arguments of the `def_percpu` attribute.
+
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::parse::{Parse, ParseStream};
+use syn::punctuated::Punctuated;
+use syn::{Error, Expr, Ident, Lit, Token, Type};
+
+/// Where a per-CPU static variable is placed in the per-CPU data area.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Placement {
+    /// The `.percpu` section, by default.
+    Default,
+    /// The `.percpu.first` section, which is placed at the beginning of the per-CPU data area, for the hottest data.
+    First,
+    /// The `.percpu.cacheline_aligned` section. The variable is aligned to and padded to a cache line, so that it
+    /// does not share cache lines with other variables.
+    CachelineAligned,
+    /// The `.percpu.read_mostly` section, which is grouped together so that rarely written variables do not share
+    /// cache lines with frequently written ones.
+    ReadMostly,
+}
+
+impl Placement {
+    fn from_ident(ident: &Ident) -> Option<Self> {
+        match ident.to_string().as_str() {
+            "first" => Some(Self::First),
+            "cacheline_aligned" => Some(Self::CachelineAligned),
+            "read_mostly" => Some(Self::ReadMostly),
+            _ => None,
+        }
+    }
+
+    /// The name of the section where the variable is placed.
+    pub fn section(&self) -> &'static str {
+        match self {
+            Self::Default => ".percpu",
+            Self::First => ".percpu.first",
+            Self::CachelineAligned => ".percpu.cacheline_aligned",
+            Self::ReadMostly => ".percpu.read_mostly",
+        }
+    }
+
+    /// The type of the inner static variable that stores the per-CPU data of type `ty`.
+    pub fn storage_type(&self, ty: &Type) -> TokenStream {
+        match self {
+            Self::CachelineAligned => quote! { percpu::__priv::CachelineAligned<#ty> },
+            _ => quote! { #ty },
+        }
+    }
+
+    /// The initialization expression of the inner static variable.
+    pub fn storage_init(&self, init_expr: &Expr) -> TokenStream {
+        match self {
+            Self::CachelineAligned => quote! { percpu::__priv::CachelineAligned(#init_expr) },
+            _ => quote! { #init_expr },
+        }
+    }
+}
+
+/// Parsed arguments of `#[def_percpu(...)]`.
+pub struct DefPercpuAttr {
+    pub placement: Placement,
+    /// Whether the initial value is all zeros, given by `zeroed`.
+    pub zeroed: bool,
+}
+
+impl DefPercpuAttr {
+    /// The name of the section where the variable is placed.
+    ///
+    /// Variables whose initial value is all zeros are placed in the `.percpu.bss` section, which is zeroed instead of
+    /// copied on initialization, unless they are placed in `.percpu.first` or `.percpu.read_mostly`. The initial value
+    /// is all zeros if `zeroed` is given, or the initialization expression is recognized by [`is_zero_init`].
+    pub fn section(&self, init_expr: &Expr) -> &'static str {
+        let zeroable = matches!(
+            self.placement,
+            Placement::Default | Placement::CachelineAligned
+        );
+        if self.zeroed || (zeroable && is_zero_init(init_expr)) {
+            ".percpu.bss"
+        } else {
+            self.placement.section()
+        }
+    }
+
+    /// Whether the initial value given by `zeroed` must be checked to be all zeros at compile time, i.e., it is not
+    /// recognized by [`is_zero_init`].
+    pub fn check_zeroed(&self, init_expr: &Expr) -> bool {
+        self.zeroed && !is_zero_init(init_expr)
+    }
+}
+
+impl Parse for DefPercpuAttr {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let args = Punctuated::<Ident, Token![,]>::parse_terminated(input)?;
+        let mut placement = Placement::Default;
+        let mut zeroed = false;
+        for arg in &args {
+            if arg == "zeroed" {
+                zeroed = true;
+                continue;
+            }
+            let Some(p) = Placement::from_ident(arg) else {
+                return Err(Error::new_spanned(
+                    arg,
+                    "expect `first`, `cacheline_aligned`, `read_mostly` or `zeroed`",
+                ));
+            };
+            if placement != Placement::Default {
+                return Err(Error::new_spanned(
+                    arg,
+                    "a per-CPU static variable can only be placed in one section",
+                ));
+            }
+            placement = p;
+        }
+        if zeroed && matches!(placement, Placement::First | Placement::ReadMostly) {
+            return Err(Error::new_spanned(
+                &args,
+                "`zeroed` cannot be used with `first` or `read_mostly`",
+            ));
+        }
+        Ok(Self { placement, zeroed })
+    }
+}
+
+/// Whether the initialization expression is obviously all zeros, i.e., `0`, `0.0`, `false`, or arrays and tuples of
+/// them.
+///
+/// Other expressions, such as struct literals and `None`, are not recognized, since their representations are not
+/// known from the syntax. Use `#[def_percpu(zeroed)]` for them.
+pub fn is_zero_init(expr: &Expr) -> bool {
+    match expr {
+        Expr::Lit(lit) => match &lit.lit {
+            Lit::Int(int) => int.base10_digits() == "0",
+            Lit::Float(float) => float
+                .base10_parse::<f64>()
+                .is_ok_and(|f| f == 0.0 && f.is_sign_positive()),
+            Lit::Bool(b) => !b.value,
+            _ => false,
+        },
+        Expr::Repeat(repeat) => is_zero_init(&repeat.expr),
+        Expr::Array(array) => array.elems.iter().all(is_zero_init),
+        Expr::Tuple(tuple) => tuple.elems.iter().all(is_zero_init),
+        Expr::Paren(paren) => is_zero_init(&paren.expr),
+        Expr::Group(group) => is_zero_init(&group.expr),
+        _ => false,
+    }
+}
@@ -0,0 +1,99 @@
+//! Accesses to the per-CPU data on the current CPU through its pointer, shared by the "sp-naive" backend and the
+//! architectures without dedicated instructions.
+
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+use syn::{Ident, Type};
+
+/// Generate a code block that applies a read-modify-write operation with a plain load and store through the given
+/// pointer expression.
+///
+/// The operation must be one of the following: `add`, `sub`, `and`, `or`, or `xor`.
+pub fn gen_op(op: &str, ptr: TokenStream, val: &Ident) -> TokenStream {
+    let update = match op {
+        "add" => quote! { *ptr = (*ptr).wrapping_add(#val) },
+        "sub" => quote! { *ptr = (*ptr).wrapping_sub(#val) },
+        "and" => quote! { *ptr &= #val },
+        "or" => quote! { *ptr |= #val },
+        "xor" => quote! { *ptr ^= #val },
+        _ => unreachable!(),
+    };
+    quote! {
+        let ptr = #ptr;
+        #update;
+    }
+}
+
+/// Generate a code block that compares the value behind the given pointer expression with `old`, and replaces it with
+/// `new` if they are equal. The code block evaluates to `Ok(previous)` on success, or `Err(current)` on failure.
+///
+/// It is an atomic of the same size as the type on the targets that support it, or a plain load and store with local
+/// interrupts disabled otherwise.
+pub fn gen_cmpxchg(ptr: TokenStream, old: &Ident, new: &Ident, ty: &Type) -> TokenStream {
+    let (atomic_ty, width) = atomic_type(ty);
+    quote! {
+        #[cfg(target_has_atomic = #width)]
+        {
+            let atomic = &*(#ptr as *const ::core::sync::atomic::#atomic_ty);
+            atomic.compare_exchange(
+                #old,
+                #new,
+                ::core::sync::atomic::Ordering::Relaxed,
+                ::core::sync::atomic::Ordering::Relaxed,
+            )
+        }
+        #[cfg(not(target_has_atomic = #width))]
+        {
+            let _guard = percpu::__priv::IrqSaveGuard::new();
+            let ptr = #ptr as *mut #ty;
+            let prev = *ptr;
+            if prev == #old {
+                *ptr = #new;
+                Ok(prev)
+            } else {
+                Err(prev)
+            }
+        }
+    }
+}
+
+/// Generate a code block that replaces the value behind the given pointer expression with `new`. The code block
+/// evaluates to the previous value.
+///
+/// It is an atomic of the same size as the type on the targets that support it, or a plain load and store with local
+/// interrupts disabled otherwise.
+pub fn gen_xchg(ptr: TokenStream, new: &Ident, ty: &Type) -> TokenStream {
+    let (atomic_ty, width) = atomic_type(ty);
+    quote! {
+        #[cfg(target_has_atomic = #width)]
+        {
+            let atomic = &*(#ptr as *const ::core::sync::atomic::#atomic_ty);
+            atomic.swap(#new, ::core::sync::atomic::Ordering::Relaxed)
+        }
+        #[cfg(not(target_has_atomic = #width))]
+        {
+            let _guard = percpu::__priv::IrqSaveGuard::new();
+            ::core::mem::replace(&mut *(#ptr as *mut #ty), #new)
+        }
+    }
+}
+
+/// Returns the atomic type in `core::sync::atomic` with the same size as the given type, and the size as the value of
+/// `target_has_atomic`.
+fn atomic_type(ty: &Type) -> (Ident, &'static str) {
+    let (name, width) = match quote!(#ty).to_string().as_str() {
+        "bool" => ("AtomicBool", "8"),
+        "u8" => ("AtomicU8", "8"),
+        "u16" => ("AtomicU16", "16"),
+        "u32" => ("AtomicU32", "32"),
+        "u64" => ("AtomicU64", "64"),
+        "usize" => ("AtomicUsize", "ptr"),
+        "i8" => ("AtomicI8", "8"),
+        "i16" => ("AtomicI16", "16"),
+        "i32" => ("AtomicI32", "32"),
+        "i64" => ("AtomicI64", "64"),
+        "isize" => ("AtomicIsize", "ptr"),
+        _ => unreachable!(),
+    };
+    (format_ident!("{}", name), width)
+}
@@ -23,8 +23,10 @@
 //!   - which can be calculated by the base address of the whole per-CPU data area and the CPU ID,
 //!   - and then stored in a register, like `TPIDR_EL1`/`TPIDR_EL2` on AArch64, or `gs` on x86_64.
 //! - The offset of the per-CPU static variable relative to the per-CPU data area base,
-//!   - which can be calculated by assembly notations, like `offset symbol` on x86_64, or `#:abs_g0_nc:symbol` on
-//!     AArch64, or `%hi(symbol)` and `%lo(symbol)` on RISC-V.
+//!   - which can be calculated by assembly notations, like `offset symbol` on x86_64, or `#:abs_g1:symbol` and
+//!     `#:abs_g0_nc:symbol` on AArch64, or `%hi(symbol)` and `%lo(symbol)` on RISC-V.
+//!   - The offset is limited to 4 GiB on AArch64 and 2 GiB on RISC-V, which is checked by the linker. Larger
+//!     offsets fail to link rather than being truncated.
 //! - The size of the per-CPU static variable,
 //!   - which we actually do not need to know, just give the right type to rust compiler.
 //!
@@ -35,16 +37,26 @@
 //!
 //! - A static variable `__PERCPU_X` with type `T` that stores the per-CPU data.
 //!
-//!   This variable is placed in the `.percpu` section. All attributes of the original static variable, as well as the
-//!   initialization expression, are preserved.
+//!   This variable is placed in the `.percpu` section, or one of its subsections (`.percpu.first`,
+//!   `.percpu.cacheline_aligned` or `.percpu.read_mostly`) according to the attribute arguments. Variables whose
+//!   initial values are all zeros are placed in `.percpu.bss` instead, which is zeroed rather than copied from the
+//!   template. All attributes of the original static variable, as well as the initialization expression, are
+//!   preserved. Variables placed in `.percpu.cacheline_aligned` are wrapped in a type that is aligned to 64 bytes.
 //!
 //!   This variable is never, and should never be, accessed directly. To access the per-CPU data, the offset of the
 //!   variable is, and should be, used.
 //!
+//! - On hosted Linux, a template of the initial value and an entry describing it in the `percpu_init` section.
+//!
+//!   The `.percpu` section is not loaded from the ELF file in this case, so `percpu::init` copies the initial values
+//!   from the templates to the per-CPU data areas.
+//!
 //! - A zero-sized wrapper struct `X_WRAPPER` that is used to access the per-CPU data.
 //!
-//!   Some methods are generated in this struct to access the per-CPU data. For primitive integer types, extra methods
-//!   are generated to accelerate the access.
+//!   Some methods are generated in this struct to access the per-CPU data. For primitive integer types, `bool`, thin
+//!   raw pointers and `Option<NonNull<T>>`, extra methods are generated to accelerate the access. So are arrays of
+//!   them, to access the elements by index. `&X_WRAPPER` can be converted to `percpu::PerCpuPtr<T>` with `From`.
+//!   `X_WRAPPER` also implements `percpu::PerCpuVar`, and `percpu::PerCpuPrimitive` for the primitive types above.
 //!
 //! - A static variable `X` of type `X_WRAPPER` that is used to access the per-CPU data.
 //!   
@@ -54,12 +66,17 @@
 #![feature(doc_cfg)]
 
 use proc_macro::TokenStream;
-use proc_macro2::Span;
 use quote::{format_ident, quote};
-use syn::{Error, ItemStatic};
+use syn::{Attribute, Error, Expr, Ident, ItemStatic, Type};
 
 #[cfg_attr(feature = "sp-naive", path = "naive.rs")]
 mod arch;
+mod attr;
+mod fallback;
+mod primitive;
+
+use self::attr::DefPercpuAttr;
+use self::primitive::Primitive;
 
 fn compiler_error(err: Error) -> TokenStream {
     err.to_compile_error().into()
@@ -69,16 +86,24 @@ fn compiler_error(err: Error) -> TokenStream {
 ///
 /// It should be used on a `static` variable definition.
 ///
+/// The variable is placed in the `.percpu` section by default. One of the following arguments can be given to place
+/// it in a subsection, which is ordered by the linker script:
+///
+/// - `#[def_percpu(first)]`: `.percpu.first`, at the beginning of the per-CPU data area, for the hottest data.
+/// - `#[def_percpu(cacheline_aligned)]`: `.percpu.cacheline_aligned`, aligned to and padded to a cache line, so that
+///   write-heavy data does not share cache lines with other data.
+/// - `#[def_percpu(read_mostly)]`: `.percpu.read_mostly`, grouped with other rarely written data.
+///
+/// Variables in `.percpu` or `.percpu.cacheline_aligned` whose initialization expressions are `0`, `0.0`, `false`,
+/// or arrays and tuples of them, are placed in `.percpu.bss` instead, which is zeroed on initialization rather than
+/// copied from the template. Add `zeroed` (e.g., `#[def_percpu(zeroed)]` or `#[def_percpu(cacheline_aligned, zeroed)]`)
+/// for other initial values that are all zeros, such as struct literals. The initial value is checked to be all zeros
+/// at compile time, where padding bytes can only be checked in struct and tuple literals.
+///
 /// See the documentation of the [percpu](https://docs.rs/percpu) crate for more details.
 #[proc_macro_attribute]
 pub fn def_percpu(attr: TokenStream, item: TokenStream) -> TokenStream {
-    if !attr.is_empty() {
-        return compiler_error(Error::new(
-            Span::call_site(),
-            "expect an empty attribute: `#[def_percpu]`",
-        ));
-    }
-
+    let attr = syn::parse_macro_input!(attr as DefPercpuAttr);
     let ast = syn::parse_macro_input!(item as ItemStatic);
 
     let attrs = &ast.attrs;
@@ -90,8 +115,7 @@ pub fn def_percpu(attr: TokenStream, item: TokenStream) -> TokenStream {
     let inner_symbol_name = &format_ident!("__PERCPU_{}", name);
     let struct_name = &format_ident!("{}_WRAPPER", name);
 
-    let ty_str = quote!(#ty).to_string();
-    let is_primitive_int = ["bool", "u8", "u16", "u32", "u64", "usize"].contains(&ty_str.as_str());
+    let primitive = Primitive::from_type(ty);
 
     let no_preempt_guard = if cfg!(feature = "preempt") {
         quote! { let _guard = percpu::__priv::NoPreemptGuard::new(); }
@@ -99,11 +123,61 @@ pub fn def_percpu(attr: TokenStream, item: TokenStream) -> TokenStream {
         quote! {}
     };
 
+    let (check_current, check_remote) = gen_debug_checks();
+
     // Do not generate `fn read_current()`, `fn write_current()`, etc for non primitive types.
-    let read_write_methods = if is_primitive_int {
-        let read_current_raw = arch::gen_read_current_raw(inner_symbol_name, ty);
-        let write_current_raw =
-            arch::gen_write_current_raw(inner_symbol_name, &format_ident!("val"), ty);
+    let read_write_methods = if let Some(prim) = &primitive {
+        let repr_ty = prim.repr();
+        let (val, old, new, value) = (
+            &format_ident!("val"),
+            &format_ident!("old"),
+            &format_ident!("new"),
+            &format_ident!("value"),
+        );
+        let read_current_raw = arch::gen_read_current_raw(inner_symbol_name, repr_ty);
+        let write_current_raw = arch::gen_write_current_raw(inner_symbol_name, val, repr_ty);
+        let cmpxchg_current_raw =
+            arch::gen_cmpxchg_current_raw(inner_symbol_name, old, new, repr_ty);
+        let xchg_current_raw = arch::gen_xchg_current_raw(inner_symbol_name, new, repr_ty);
+        let op_guard = arch::gen_op_guard(&no_preempt_guard);
+
+        // Pointer-like types are accessed as `usize`, convert them at the boundaries.
+        let (read_current_raw, write_current_raw, cmpxchg_current_raw, xchg_current_raw) =
+            if prim.needs_conversion() {
+                let (val_repr, old_repr, new_repr) =
+                    (prim.encode(val), prim.encode(old), prim.encode(new));
+                let from_repr = prim.decode(value);
+                (
+                    quote! {
+                        let value: #repr_ty = { #read_current_raw };
+                        #from_repr
+                    },
+                    quote! {
+                        let val = #val_repr;
+                        #write_current_raw
+                    },
+                    quote! {
+                        let (old, new) = (#old_repr, #new_repr);
+                        let res: Result<#repr_ty, #repr_ty> = { #cmpxchg_current_raw };
+                        match res {
+                            Ok(value) => Ok(#from_repr),
+                            Err(value) => Err(#from_repr),
+                        }
+                    },
+                    quote! {
+                        let new = #new_repr;
+                        let value: #repr_ty = { #xchg_current_raw };
+                        #from_repr
+                    },
+                )
+            } else {
+                (
+                    read_current_raw,
+                    write_current_raw,
+                    cmpxchg_current_raw,
+                    xchg_current_raw,
+                )
+            };
 
         quote! {
             /// Returns the value of the per-CPU static variable on the current CPU.
@@ -113,6 +187,7 @@ pub fn def_percpu(attr: TokenStream, item: TokenStream) -> TokenStream {
             /// Caller must ensure that preemption is disabled on the current CPU.
             #[inline]
             pub unsafe fn read_current_raw(&self) -> #ty {
+                #check_current
                 #read_current_raw
             }
 
@@ -123,6 +198,7 @@ pub fn def_percpu(attr: TokenStream, item: TokenStream) -> TokenStream {
             /// Caller must ensure that preemption is disabled on the current CPU.
             #[inline]
             pub unsafe fn write_current_raw(&self, val: #ty) {
+                #check_current
                 #write_current_raw
             }
 
@@ -139,9 +215,256 @@ pub fn def_percpu(attr: TokenStream, item: TokenStream) -> TokenStream {
                 #no_preempt_guard
                 unsafe { self.write_current_raw(val) }
             }
+
+            /// Stores `new` into the per-CPU static variable on the current CPU if its value is equal to `old`.
+            ///
+            /// Returns `Ok(previous)` if the value was replaced, or `Err(current)` otherwise. The operation is atomic
+            /// with respect to interrupts on the current CPU, but has no ordering guarantees to other CPUs.
+            ///
+            /// # Safety
+            ///
+            /// Caller must ensure that preemption is disabled on the current CPU.
+            #[inline]
+            pub unsafe fn cmpxchg_current_raw(&self, old: #ty, new: #ty) -> Result<#ty, #ty> {
+                #check_current
+                #cmpxchg_current_raw
+            }
+
+            /// Stores `new` into the per-CPU static variable on the current CPU, and returns the previous value.
+            ///
+            /// The operation is atomic with respect to interrupts on the current CPU, but has no ordering guarantees
+            /// to other CPUs.
+            ///
+            /// # Safety
+            ///
+            /// Caller must ensure that preemption is disabled on the current CPU.
+            #[inline]
+            pub unsafe fn xchg_current_raw(&self, new: #ty) -> #ty {
+                #check_current
+                #xchg_current_raw
+            }
+
+            /// Stores `new` into the per-CPU static variable on the current CPU if its value is equal to `old`.
+            ///
+            /// Returns `Ok(previous)` if the value was replaced, or `Err(current)` otherwise. The operation is atomic
+            /// with respect to interrupts on the current CPU. On x86_64, it is a single non-locked `cmpxchg`
+            /// instruction. On other architectures, preemption will be disabled during the call.
+            #[inline]
+            pub fn cmpxchg_current(&self, old: #ty, new: #ty) -> Result<#ty, #ty> {
+                #op_guard
+                unsafe { self.cmpxchg_current_raw(old, new) }
+            }
+
+            /// Stores `new` into the per-CPU static variable on the current CPU, and returns the previous value.
+            ///
+            /// The operation is atomic with respect to interrupts on the current CPU. On x86_64, it is a non-locked
+            /// `cmpxchg` loop. On other architectures, preemption will be disabled during the call.
+            #[inline]
+            pub fn xchg_current(&self, new: #ty) -> #ty {
+                #op_guard
+                unsafe { self.xchg_current_raw(new) }
+            }
+
+            /// Returns the value of the per-CPU static variable on the given CPU.
+            ///
+            /// The value is read with volatile semantics, so it is never cached by the compiler.
+            ///
+            /// # Safety
+            ///
+            /// Caller must ensure that
+            /// - the CPU ID is valid, and
+            /// - data races will not happen.
+            #[inline]
+            pub unsafe fn read_remote_raw(&self, cpu_id: usize) -> #ty {
+                ::core::ptr::read_volatile(self.remote_ptr(cpu_id))
+            }
+
+            /// Set the value of the per-CPU static variable on the given CPU.
+            ///
+            /// The value is written with volatile semantics, so it is never cached by the compiler.
+            ///
+            /// # Safety
+            ///
+            /// Caller must ensure that
+            /// - the CPU ID is valid, and
+            /// - data races will not happen.
+            #[inline]
+            pub unsafe fn write_remote_raw(&self, cpu_id: usize, val: #ty) {
+                ::core::ptr::write_volatile(self.remote_ptr(cpu_id) as *mut #ty, val)
+            }
+
+            /// Returns the value of the per-CPU static variable on the given CPU.
+            ///
+            /// The value is read with volatile semantics, so it is never cached by the compiler.
+            ///
+            /// # Safety
+            ///
+            /// Caller must ensure that data races will not happen.
+            ///
+            /// # Panics
+            ///
+            /// Panics if `cpu_id` is not less than [`percpu_area_num`](percpu::percpu_area_num).
+            pub unsafe fn read_remote(&self, cpu_id: usize) -> #ty {
+                assert!(cpu_id < percpu::percpu_area_num(), "invalid CPU ID: {}", cpu_id);
+                self.read_remote_raw(cpu_id)
+            }
+
+            /// Set the value of the per-CPU static variable on the given CPU.
+            ///
+            /// The value is written with volatile semantics, so it is never cached by the compiler.
+            ///
+            /// # Safety
+            ///
+            /// Caller must ensure that data races will not happen.
+            ///
+            /// # Panics
+            ///
+            /// Panics if `cpu_id` is not less than [`percpu_area_num`](percpu::percpu_area_num).
+            pub unsafe fn write_remote(&self, cpu_id: usize, val: #ty) {
+                assert!(cpu_id < percpu::percpu_area_num(), "invalid CPU ID: {}", cpu_id);
+                self.write_remote_raw(cpu_id, val)
+            }
         }
+    } else {
+        quote! {}
+    };
+
+    // Only generate `fn add_current()`, `fn sub_current()`, etc for primitive integer types.
+    let op_methods = if primitive.as_ref().is_some_and(Primitive::is_int) {
+        let val = &format_ident!("val");
+        let op_guard = arch::gen_op_guard(&no_preempt_guard);
+        let ops = [
+            ("add", "Adds `val` to", "wrapping around on overflow"),
+            ("sub", "Subtracts `val` from", "wrapping around on overflow"),
+            (
+                "and",
+                "Performs a bitwise AND of `val` and",
+                "storing the result",
+            ),
+            (
+                "or",
+                "Performs a bitwise OR of `val` and",
+                "storing the result",
+            ),
+            (
+                "xor",
+                "Performs a bitwise XOR of `val` and",
+                "storing the result",
+            ),
+        ];
+        let methods = ops.iter().map(|(op, action, result)| {
+            let op_current_raw = arch::gen_op_current_raw(inner_symbol_name, op, val, ty);
+            let raw_name = format_ident!("{}_current_raw", op);
+            let name = format_ident!("{}_current", op);
+            let raw_doc = format!(" {action} the per-CPU static variable on the current CPU, {result}.");
+            let doc = format!(
+                " {action} the per-CPU static variable on the current CPU, {result}. On x86_64, this is a single \
+                instruction that can be neither preempted nor interrupted. On other architectures, preemption will be \
+                disabled during the call."
+            );
+            quote! {
+                #[doc = #raw_doc]
+                ///
+                /// # Safety
+                ///
+                /// Caller must ensure that preemption is disabled on the current CPU.
+                #[inline]
+                pub unsafe fn #raw_name(&self, val: #ty) {
+                    #check_current
+                    #op_current_raw
+                }
+
+                #[doc = #doc]
+                #[inline]
+                pub fn #name(&self, val: #ty) {
+                    #op_guard
+                    unsafe { self.#raw_name(val) }
+                }
+            }
+        });
+
+        quote! {
+            #(#methods)*
+
+            /// Increments the per-CPU static variable on the current CPU by one, wrapping around on overflow.
+            ///
+            /// See [`add_current`](Self::add_current) for the preemption and interrupt safety.
+            #[inline]
+            pub fn inc_current(&self) {
+                self.add_current(1)
+            }
 
-        // Todo: maybe add `(read|write)_remote(_raw)?` here?
+            /// Decrements the per-CPU static variable on the current CPU by one, wrapping around on overflow.
+            ///
+            /// See [`sub_current`](Self::sub_current) for the preemption and interrupt safety.
+            #[inline]
+            pub fn dec_current(&self) {
+                self.sub_current(1)
+            }
+        }
+    } else {
+        quote! {}
+    };
+
+    // Generate `fn read_current_at()`, `fn write_current_at()`, etc for arrays of primitive types.
+    let array_methods = match &**ty {
+        Type::Array(array) => Primitive::from_type(&array.elem).map(|elem| {
+            gen_array_methods(
+                inner_symbol_name,
+                &array.elem,
+                &array.len,
+                &elem,
+                &no_preempt_guard,
+                &check_current,
+            )
+        }),
+        _ => None,
+    }
+    .unwrap_or_default();
+
+    let primitive_impl = if primitive.is_some() {
+        quote! {
+            impl percpu::PerCpuPrimitive for #struct_name {
+                #[inline]
+                unsafe fn read_current_raw(&self) -> #ty {
+                    Self::read_current_raw(self)
+                }
+
+                #[inline]
+                unsafe fn write_current_raw(&self, val: #ty) {
+                    Self::write_current_raw(self, val)
+                }
+
+                #[inline]
+                fn read_current(&self) -> #ty {
+                    Self::read_current(self)
+                }
+
+                #[inline]
+                fn write_current(&self, val: #ty) {
+                    Self::write_current(self, val)
+                }
+            }
+        }
+    } else {
+        quote! {}
+    };
+
+    let section = attr.section(init_expr);
+    let storage_ty = attr.placement.storage_type(ty);
+    let storage_init = attr.placement.storage_init(init_expr);
+
+    // The per-CPU data areas are zeroed before the initial values are copied, nothing to do for `.percpu.bss`.
+    let hosted_init = if section == ".percpu.bss" {
+        quote! {}
+    } else {
+        arch::gen_hosted_init(inner_symbol_name, &storage_ty, &storage_init)
+    };
+
+    // `zeroed` places the variable in `.percpu.bss` regardless of its initial value, so check at compile time that
+    // the initial value is all zeros if it is not obvious from the syntax.
+    let zeroed_check = if attr.check_zeroed(init_expr) {
+        gen_zeroed_check(name, attrs, ty, init_expr)
     } else {
         quote! {}
     };
@@ -149,9 +472,13 @@ pub fn def_percpu(attr: TokenStream, item: TokenStream) -> TokenStream {
     let offset = arch::gen_offset(inner_symbol_name);
     let current_ptr = arch::gen_current_ptr(inner_symbol_name, ty);
     quote! {
-        #[cfg_attr(not(target_os = "macos"), link_section = ".percpu")] // unimplemented on macos
+        #zeroed_check
+
+        #[cfg_attr(not(target_os = "macos"), link_section = #section)] // unimplemented on macos
         #(#attrs)*
-        static mut #inner_symbol_name: #ty = #init_expr;
+        static mut #inner_symbol_name: #storage_ty = #storage_init;
+
+        #hosted_init
 
         #[doc = concat!("Wrapper struct for the per-CPU data [`", stringify!(#name), "`]")]
         #[allow(non_camel_case_types)]
@@ -160,6 +487,42 @@ pub fn def_percpu(attr: TokenStream, item: TokenStream) -> TokenStream {
         #(#attrs)*
         #vis static #name: #struct_name = #struct_name {};
 
+        impl percpu::PerCpuVar for #struct_name {
+            type Target = #ty;
+
+            #[inline]
+            fn offset(&self) -> usize {
+                Self::offset(self)
+            }
+
+            #[inline]
+            unsafe fn current_ptr(&self) -> *const #ty {
+                Self::current_ptr(self)
+            }
+
+            #[inline]
+            unsafe fn remote_ptr(&self, cpu_id: usize) -> *const #ty {
+                Self::remote_ptr(self, cpu_id)
+            }
+
+            #[inline]
+            fn with_current<F, R>(&self, f: F) -> R
+            where
+                F: FnOnce(&mut #ty) -> R,
+            {
+                Self::with_current(self, f)
+            }
+        }
+
+        #primitive_impl
+
+        impl ::core::convert::From<&#struct_name> for percpu::PerCpuPtr<#ty> {
+            #[inline]
+            fn from(var: &#struct_name) -> Self {
+                unsafe { percpu::PerCpuPtr::from_offset(var.offset()) }
+            }
+        }
+
         impl #struct_name {
             /// Returns the offset relative to the per-CPU data area base.
             #[inline]
@@ -174,6 +537,7 @@ pub fn def_percpu(attr: TokenStream, item: TokenStream) -> TokenStream {
             /// Caller must ensure that preemption is disabled on the current CPU.
             #[inline]
             pub unsafe fn current_ptr(&self) -> *const #ty {
+                #check_current
                 #current_ptr
             }
 
@@ -198,6 +562,38 @@ pub fn def_percpu(attr: TokenStream, item: TokenStream) -> TokenStream {
                 &mut *(self.current_ptr() as *mut #ty)
             }
 
+            /// Returns the reference of the per-CPU static variable on the current CPU.
+            ///
+            /// The [`PreemptToken`](percpu::PreemptToken) proves that preemption is disabled while the reference is
+            /// alive.
+            ///
+            /// # Safety
+            ///
+            /// Caller must ensure that the per-CPU static variable on the current CPU is not modified while the
+            /// reference is alive, e.g., with `write_current`, `with_current` or `current_mut`, including in
+            /// interrupt handlers.
+            #[inline]
+            pub unsafe fn current_ref<'t>(&self, _token: &'t percpu::PreemptToken<'_>) -> &'t #ty {
+                &*self.current_ptr()
+            }
+
+            /// Returns the mutable reference of the per-CPU static variable on the current CPU.
+            ///
+            /// The [`PreemptToken`](percpu::PreemptToken) proves that preemption is disabled while the reference is
+            /// alive. The token is borrowed immutably, so that the mutable references of different per-CPU static
+            /// variables can be alive at the same time.
+            ///
+            /// # Safety
+            ///
+            /// Caller must ensure that the per-CPU static variable on the current CPU is not accessed other than
+            /// through the reference while it is alive, e.g., with `read_current`, `with_current`, or another
+            /// `current_ref` or `current_mut`, including in interrupt handlers.
+            #[inline]
+            #[allow(clippy::mut_from_ref)]
+            pub unsafe fn current_mut<'t>(&self, _token: &'t percpu::PreemptToken<'_>) -> &'t mut #ty {
+                &mut *(self.current_ptr() as *mut #ty)
+            }
+
             /// Manipulate the per-CPU data on the current CPU in the given closure.
             /// Preemption will be disabled during the call.
             pub fn with_current<F, T>(&self, f: F) -> T
@@ -208,6 +604,42 @@ pub fn def_percpu(attr: TokenStream, item: TokenStream) -> TokenStream {
                 f(unsafe { self.current_ref_mut_raw() })
             }
 
+            // The `for<'a>` in the following `where` clauses prevents the bounds from being rejected as trivially
+            // false for the types that do not implement the trait.
+
+            /// Returns a copy of the per-CPU data on the current CPU.
+            /// Preemption will be disabled during the call.
+            pub fn get_current(&self) -> #ty
+            where
+                for<'a> #ty: Copy,
+            {
+                #no_preempt_guard
+                unsafe { *self.current_ptr() }
+            }
+
+            /// Set the per-CPU data on the current CPU to `val`, dropping the previous value.
+            /// Preemption will be disabled during the call, but not when dropping the previous value.
+            pub fn set_current(&self, val: #ty) {
+                let _ = self.replace_current(val);
+            }
+
+            /// Replace the per-CPU data on the current CPU with `val`, and return the previous value.
+            /// Preemption will be disabled during the call.
+            pub fn replace_current(&self, val: #ty) -> #ty {
+                #no_preempt_guard
+                ::core::mem::replace(unsafe { self.current_ref_mut_raw() }, val)
+            }
+
+            /// Replace the per-CPU data on the current CPU with its default value, and return the previous value.
+            /// Preemption will be disabled during the call.
+            pub fn take_current(&self) -> #ty
+            where
+                for<'a> #ty: Default,
+            {
+                #no_preempt_guard
+                ::core::mem::take(unsafe { self.current_ref_mut_raw() })
+            }
+
             /// Returns the raw pointer of this per-CPU static variable on the given CPU.
             ///
             /// # Safety
@@ -217,6 +649,13 @@ pub fn def_percpu(attr: TokenStream, item: TokenStream) -> TokenStream {
             /// - data races will not happen.
             #[inline]
             pub unsafe fn remote_ptr(&self, cpu_id: usize) -> *const #ty {
+                #check_remote
+                debug_assert!(
+                    cpu_id < percpu::percpu_area_capacity(),
+                    "CPU ID {} exceeds the per-CPU area capacity {}",
+                    cpu_id,
+                    percpu::percpu_area_capacity(),
+                );
                 let base = percpu::percpu_area_base(cpu_id);
                 let offset = #offset;
                 (base + offset) as *const #ty
@@ -248,8 +687,285 @@ pub fn def_percpu(attr: TokenStream, item: TokenStream) -> TokenStream {
             }
 
             #read_write_methods
+            #op_methods
+            #array_methods
+        }
+    }
+    .into()
+}
+
+/// Generate the statements that check the per-CPU accesses on the current CPU and on the given CPU (`cpu_id`), when
+/// the `debug-checks` feature is enabled.
+fn gen_debug_checks() -> (proc_macro2::TokenStream, proc_macro2::TokenStream) {
+    if !cfg!(feature = "debug-checks") {
+        return (quote! {}, quote! {});
+    }
+    // All CPUs share the same global data for "sp-naive" use, so accesses on the current CPU are always valid.
+    let check_current = if cfg!(feature = "sp-naive") {
+        quote! {}
+    } else {
+        quote! { percpu::__priv::check_current(); }
+    };
+    let check_remote = quote! { percpu::__priv::check_remote(cpu_id); };
+    (check_current, check_remote)
+}
+
+/// Generate a constant item that fails to evaluate if the initial value of the per-CPU static variable `name` is not
+/// all zeros.
+///
+/// The initial value is written into zeroed memory and checked byte by byte. Struct and tuple literals are written
+/// field by field, so that their padding bytes are kept zero rather than uninitialized, which can not be checked. If
+/// all the fields are recognized by [`is_zero_init`](attr::is_zero_init), the bytes are not checked, but the fields
+/// are still written, so that the literals of enum variants are rejected.
+fn gen_zeroed_check(
+    name: &Ident,
+    attrs: &[Attribute],
+    ty: &Type,
+    init_expr: &Expr,
+) -> proc_macro2::TokenStream {
+    /// Returns the writes, and whether any of the written values is not recognized as all zeros.
+    fn gen_writes(
+        place: proc_macro2::TokenStream,
+        expr: &Expr,
+    ) -> (proc_macro2::TokenStream, bool) {
+        let fields: Vec<_> = match expr {
+            Expr::Struct(lit) if lit.rest.is_none() => lit
+                .fields
+                .iter()
+                .map(|field| {
+                    let member = &field.member;
+                    gen_writes(quote! { #place.#member }, &field.expr)
+                })
+                .collect(),
+            Expr::Tuple(tuple) => tuple
+                .elems
+                .iter()
+                .enumerate()
+                .map(|(i, elem)| {
+                    let member = syn::Index::from(i);
+                    gen_writes(quote! { #place.#member }, elem)
+                })
+                .collect(),
+            Expr::Paren(paren) => return gen_writes(place, &paren.expr),
+            Expr::Group(group) => return gen_writes(place, &group.expr),
+            _ => {
+                let write = quote! {
+                    ::core::ptr::write_unaligned(::core::ptr::addr_of_mut!(#place), #expr);
+                };
+                return (write, !attr::is_zero_init(expr));
+            }
+        };
+        let unknown = fields.iter().any(|(_, unknown)| *unknown);
+        (
+            fields.into_iter().map(|(write, _)| write).collect(),
+            unknown,
+        )
+    }
+
+    let cfg_attrs = attrs.iter().filter(|attr| attr.path().is_ident("cfg"));
+    let (writes, unknown) = gen_writes(quote! { (*ptr) }, init_expr);
+    let check = if unknown {
+        let msg = format!(
+            "the initial value of `{name}` is not all zeros, remove `zeroed` from `def_percpu`"
+        );
+        quote! {
+            let bytes: [u8; ::core::mem::size_of::<#ty>()] = unsafe { ::core::mem::transmute(value) };
+            assert!(percpu::__priv::is_all_zeros(&bytes), #msg);
         }
+    } else {
+        quote! {}
+    };
+    quote! {
+        #(#cfg_attrs)*
+        const _: () = {
+            let mut value = ::core::mem::MaybeUninit::<#ty>::zeroed();
+            let ptr = value.as_mut_ptr();
+            unsafe { #writes }
+            #check
+        };
     }
+}
+
+/// Generate the methods to access the elements of a per-CPU array, whose element type is a primitive type.
+fn gen_array_methods(
+    symbol: &Ident,
+    elem_ty: &Type,
+    len: &Expr,
+    elem: &Primitive,
+    no_preempt_guard: &proc_macro2::TokenStream,
+    check_current: &proc_macro2::TokenStream,
+) -> proc_macro2::TokenStream {
+    let repr_ty = elem.repr();
+    let (idx, val, value) = (
+        &format_ident!("idx"),
+        &format_ident!("val"),
+        &format_ident!("value"),
+    );
+    let read_current_at_raw = arch::gen_read_current_at_raw(symbol, idx, repr_ty);
+    let write_current_at_raw = arch::gen_write_current_at_raw(symbol, idx, val, repr_ty);
+    let (read_current_at_raw, write_current_at_raw) = if elem.needs_conversion() {
+        let (decode, encode) = (elem.decode(value), elem.encode(val));
+        (
+            quote! {
+                let value: #repr_ty = { #read_current_at_raw };
+                #decode
+            },
+            quote! {
+                let val = #encode;
+                #write_current_at_raw
+            },
+        )
+    } else {
+        (read_current_at_raw, write_current_at_raw)
+    };
+    let bounds_check = quote! {
+        #check_current
+        let len: usize = #len;
+        assert!(idx < len, "index out of bounds: the len is {} but the index is {}", len, idx);
+    };
+
+    let add_methods = if elem.is_int() {
+        let add_current_at_raw = arch::gen_op_current_at_raw(symbol, "add", idx, val, repr_ty);
+        let op_guard = arch::gen_op_guard(no_preempt_guard);
+        quote! {
+            /// Adds `val` to the element at `idx` of the per-CPU array on the current CPU, wrapping around on
+            /// overflow.
+            ///
+            /// # Safety
+            ///
+            /// Caller must ensure that preemption is disabled on the current CPU.
+            ///
+            /// # Panics
+            ///
+            /// Panics if `idx` is out of bounds.
+            #[inline]
+            pub unsafe fn add_current_at_raw(&self, idx: usize, val: #elem_ty) {
+                #bounds_check
+                #add_current_at_raw
+            }
+
+            /// Adds `val` to the element at `idx` of the per-CPU array on the current CPU, wrapping around on
+            /// overflow. On x86_64, this is a single instruction that can be neither preempted nor interrupted. On
+            /// other architectures, preemption will be disabled during the call.
+            ///
+            /// # Panics
+            ///
+            /// Panics if `idx` is out of bounds.
+            #[inline]
+            pub fn add_current_at(&self, idx: usize, val: #elem_ty) {
+                #op_guard
+                unsafe { self.add_current_at_raw(idx, val) }
+            }
+        }
+    } else {
+        quote! {}
+    };
+
+    quote! {
+        /// Returns the element at `idx` of the per-CPU array on the current CPU.
+        ///
+        /// # Safety
+        ///
+        /// Caller must ensure that preemption is disabled on the current CPU.
+        ///
+        /// # Panics
+        ///
+        /// Panics if `idx` is out of bounds.
+        #[inline]
+        pub unsafe fn read_current_at_raw(&self, idx: usize) -> #elem_ty {
+            #bounds_check
+            #read_current_at_raw
+        }
+
+        /// Set the element at `idx` of the per-CPU array on the current CPU.
+        ///
+        /// # Safety
+        ///
+        /// Caller must ensure that preemption is disabled on the current CPU.
+        ///
+        /// # Panics
+        ///
+        /// Panics if `idx` is out of bounds.
+        #[inline]
+        pub unsafe fn write_current_at_raw(&self, idx: usize, val: #elem_ty) {
+            #bounds_check
+            #write_current_at_raw
+        }
+
+        /// Returns the element at `idx` of the per-CPU array on the current CPU. Preemption will be disabled during
+        /// the call.
+        ///
+        /// # Panics
+        ///
+        /// Panics if `idx` is out of bounds.
+        pub fn read_current_at(&self, idx: usize) -> #elem_ty {
+            #no_preempt_guard
+            unsafe { self.read_current_at_raw(idx) }
+        }
+
+        /// Set the element at `idx` of the per-CPU array on the current CPU. Preemption will be disabled during the
+        /// call.
+        ///
+        /// # Panics
+        ///
+        /// Panics if `idx` is out of bounds.
+        pub fn write_current_at(&self, idx: usize, val: #elem_ty) {
+            #no_preempt_guard
+            unsafe { self.write_current_at_raw(idx, val) }
+        }
+
+        #add_methods
+    }
+}
+
+/// Projects a field of a per-CPU static variable to a [`PerCpuField`](https://docs.rs/percpu/latest/percpu/type.PerCpuField.html).
+///
+/// The argument is a per-CPU static variable followed by one or more field accesses, e.g. `percpu_field!(STATS.rx)`
+/// or `percpu_field!(STATS.queue.len)`. The variable can be any per-CPU variable given by its path, e.g., a static
+/// variable defined with `def_percpu`. The fields must be reached without dereferencing, e.g., through a reference or
+/// a `Box`, which is rejected at compile time.
+///
+/// See the documentation of the [percpu](https://docs.rs/percpu) crate for more details.
+#[proc_macro]
+pub fn percpu_field(item: TokenStream) -> TokenStream {
+    let expr = syn::parse_macro_input!(item as Expr);
+
+    // Split `VAR.a.b` into the variable path `VAR` and the fields `a.b`.
+    let mut fields = Vec::new();
+    let mut base = &expr;
+    while let Expr::Field(field) = base {
+        fields.push(&field.member);
+        base = &field.base;
+    }
+    fields.reverse();
+    let var = match base {
+        Expr::Path(path) if !fields.is_empty() && path.qself.is_none() => &path.path,
+        _ => {
+            return compiler_error(Error::new_spanned(
+                expr,
+                "expect a field of a per-CPU static variable: `percpu_field!(VAR.field)`",
+            ))
+        }
+    };
+
+    // The type of the variable is inferred from its value, and the field is projected one step at a time, checking
+    // that none of the projected data implements `Deref`, so that auto-deref never reads the uninitialized data.
+    let steps = fields.iter().map(|field| {
+        quote! {
+            let _: percpu::__priv::NotDeref = (&percpu::__priv::FieldProbe::of(ptr)).check();
+            let ptr = ::core::ptr::addr_of!((*ptr).#field);
+        }
+    });
+    quote! {{
+        #[allow(unused_imports)]
+        use percpu::__priv::{DerefCheck as _, NotDerefCheck as _};
+        unsafe {
+            percpu::PerCpuPtr::__field(&#var, |ptr| {
+                #(#steps)*
+                ptr
+            })
+        }
+    }}
     .into()
 }
 
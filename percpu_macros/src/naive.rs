@@ -3,21 +3,32 @@
 use quote::quote;
 use syn::{Ident, Type};
 
+use crate::fallback;
+
 pub fn gen_offset(symbol: &Ident) -> proc_macro2::TokenStream {
     quote! {
         unsafe { ::core::ptr::addr_of!(#symbol) as usize }
     }
 }
 
-pub fn gen_current_ptr(symbol: &Ident, _ty: &Type) -> proc_macro2::TokenStream {
+/// No initial values need to be recorded for "sp-naive" use.
+pub fn gen_hosted_init(
+    _symbol: &Ident,
+    _storage_ty: &proc_macro2::TokenStream,
+    _storage_init: &proc_macro2::TokenStream,
+) -> proc_macro2::TokenStream {
+    quote! {}
+}
+
+pub fn gen_current_ptr(symbol: &Ident, ty: &Type) -> proc_macro2::TokenStream {
     quote! {
-        unsafe { ::core::ptr::addr_of!(#symbol) }
+        unsafe { ::core::ptr::addr_of!(#symbol).cast::<#ty>() }
     }
 }
 
-pub fn gen_read_current_raw(_symbol: &Ident, _ty: &Type) -> proc_macro2::TokenStream {
+pub fn gen_read_current_raw(_symbol: &Ident, ty: &Type) -> proc_macro2::TokenStream {
     quote! {
-        *self.current_ptr()
+        *self.current_ptr().cast::<#ty>()
     }
 }
 
@@ -26,3 +37,64 @@ pub fn gen_write_current_raw(_symbol: &Ident, val: &Ident, ty: &Type) -> proc_ma
         *(self.current_ptr() as *mut #ty) = #val
     }
 }
+
+pub fn gen_op_current_raw(
+    _symbol: &Ident,
+    op: &str,
+    val: &Ident,
+    ty: &Type,
+) -> proc_macro2::TokenStream {
+    fallback::gen_op(op, quote! { self.current_ptr() as *mut #ty }, val)
+}
+
+pub fn gen_op_guard(no_preempt_guard: &proc_macro2::TokenStream) -> proc_macro2::TokenStream {
+    no_preempt_guard.clone()
+}
+
+pub fn gen_cmpxchg_current_raw(
+    _symbol: &Ident,
+    old: &Ident,
+    new: &Ident,
+    ty: &Type,
+) -> proc_macro2::TokenStream {
+    fallback::gen_cmpxchg(quote! { self.current_ptr() }, old, new, ty)
+}
+
+pub fn gen_xchg_current_raw(_symbol: &Ident, new: &Ident, ty: &Type) -> proc_macro2::TokenStream {
+    fallback::gen_xchg(quote! { self.current_ptr() }, new, ty)
+}
+
+pub fn gen_read_current_at_raw(
+    _symbol: &Ident,
+    idx: &Ident,
+    ty: &Type,
+) -> proc_macro2::TokenStream {
+    quote! {
+        *self.current_ptr().cast::<#ty>().add(#idx)
+    }
+}
+
+pub fn gen_write_current_at_raw(
+    _symbol: &Ident,
+    idx: &Ident,
+    val: &Ident,
+    ty: &Type,
+) -> proc_macro2::TokenStream {
+    quote! {
+        *(self.current_ptr() as *mut #ty).add(#idx) = #val
+    }
+}
+
+pub fn gen_op_current_at_raw(
+    _symbol: &Ident,
+    op: &str,
+    idx: &Ident,
+    val: &Ident,
+    ty: &Type,
+) -> proc_macro2::TokenStream {
+    fallback::gen_op(
+        op,
+        quote! { (self.current_ptr() as *mut #ty).add(#idx) },
+        val,
+    )
+}
@@ -0,0 +1,118 @@
+//! Types that are accessed with the fast path, i.e., a single load or store relative to the thread pointer register.
+
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{GenericArgument, Ident, PathArguments, Type};
+
+enum PrimitiveKind {
+    /// `bool`.
+    Bool,
+    /// Primitive integer types, i.e., `u8`..`u64`, `usize`, `i8`..`i64`, and `isize`.
+    Int,
+    /// Thin raw pointers, i.e., `*const T` and `*mut T`.
+    Ptr,
+    /// `Option<NonNull<T>>`.
+    NonNull,
+}
+
+/// A type that is accessed with the fast path.
+///
+/// Pointer-like types are accessed as `usize` (the "repr" type), the other types are accessed as themselves.
+pub struct Primitive<'a> {
+    ty: &'a Type,
+    kind: PrimitiveKind,
+    repr: Type,
+}
+
+impl<'a> Primitive<'a> {
+    /// Returns `None` if the type is not supported by the fast path.
+    pub fn from_type(ty: &'a Type) -> Option<Self> {
+        let ty_str = quote!(#ty).to_string();
+        let kind = match ty_str.as_str() {
+            "bool" => PrimitiveKind::Bool,
+            "u8" | "u16" | "u32" | "u64" | "usize" | "i8" | "i16" | "i32" | "i64" | "isize" => {
+                PrimitiveKind::Int
+            }
+            _ if is_thin_ptr(ty) => PrimitiveKind::Ptr,
+            _ if is_option_non_null(ty) => PrimitiveKind::NonNull,
+            _ => return None,
+        };
+        let repr = match kind {
+            PrimitiveKind::Bool | PrimitiveKind::Int => ty.clone(),
+            PrimitiveKind::Ptr | PrimitiveKind::NonNull => syn::parse_quote!(usize),
+        };
+        Some(Self { ty, kind, repr })
+    }
+
+    /// Whether arithmetic and bitwise operations are supported.
+    pub fn is_int(&self) -> bool {
+        matches!(self.kind, PrimitiveKind::Int)
+    }
+
+    /// The type used to access the value in the generated assembly.
+    pub fn repr(&self) -> &Type {
+        &self.repr
+    }
+
+    /// Generate an expression that converts `val` of the original type to the repr type.
+    pub fn encode(&self, val: &Ident) -> TokenStream {
+        match self.kind {
+            PrimitiveKind::Bool | PrimitiveKind::Int => quote! { #val },
+            PrimitiveKind::Ptr => quote! { #val as usize },
+            PrimitiveKind::NonNull => quote! { #val.map_or(0, |p| p.as_ptr() as usize) },
+        }
+    }
+
+    /// Generate an expression that converts `value` of the repr type to the original type.
+    pub fn decode(&self, value: &Ident) -> TokenStream {
+        let ty = self.ty;
+        match self.kind {
+            PrimitiveKind::Bool | PrimitiveKind::Int => quote! { #value },
+            PrimitiveKind::Ptr => quote! { #value as #ty },
+            PrimitiveKind::NonNull => quote! { ::core::ptr::NonNull::new(#value as *mut _) },
+        }
+    }
+
+    /// Whether conversions between the original type and the repr type are needed.
+    pub fn needs_conversion(&self) -> bool {
+        matches!(self.kind, PrimitiveKind::Ptr | PrimitiveKind::NonNull)
+    }
+}
+
+fn is_thin_ptr(ty: &Type) -> bool {
+    matches!(ty, Type::Ptr(ptr) if is_sized_pointee(&ptr.elem))
+}
+
+/// Pointers to slices, `str` and trait objects are fat pointers, which cannot be accessed as `usize`.
+fn is_sized_pointee(elem: &Type) -> bool {
+    match elem {
+        Type::Slice(_) | Type::TraitObject(_) => false,
+        Type::Path(path) => !path.path.is_ident("str"),
+        _ => true,
+    }
+}
+
+/// Matches `Option<NonNull<T>>`, with or without the module paths.
+fn is_option_non_null(ty: &Type) -> bool {
+    generic_arg_of(ty, "Option")
+        .and_then(|inner| generic_arg_of(inner, "NonNull"))
+        .is_some_and(is_sized_pointee)
+}
+
+/// Returns `T` if the type is `Name<T>`, with or without the module paths.
+fn generic_arg_of<'a>(ty: &'a Type, name: &str) -> Option<&'a Type> {
+    let Type::Path(path) = ty else {
+        return None;
+    };
+    let segment = path.path.segments.last()?;
+    if path.qself.is_some() || segment.ident != name {
+        return None;
+    }
+    let PathArguments::AngleBracketed(args) = &segment.arguments else {
+        return None;
+    };
+    match args.args.first() {
+        Some(GenericArgument::Type(arg)) if args.args.len() == 1 => Some(arg),
+        _ => None,
+    }
+}